@@ -0,0 +1,91 @@
+//! Which directory attributes fill in which fields of a [`Person`](crate::directory::Person).
+//!
+//! Set per institution as the `attributes` of its profile, e.g.
+//!
+//! ```json
+//! { "classification": "utexasEduPersonClassification", "title": "title" }
+//! ```
+//!
+//! Fields left out keep their default UT attribute. Optional fields can be set to `null`
+//! where the directory has no such attribute, which leaves them empty in every token.
+
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct AttributeMap {
+    /// Attribute people are searched for by
+    pub search: String,
+    /// Attribute holding the EID, compared against the one searched for
+    pub eid: String,
+    pub name: String,
+    pub email: String,
+    pub major: String,
+    pub school: String,
+    pub affiliation: String,
+    pub classification: Option<String>,
+    pub department: Option<String>,
+    pub title: Option<String>,
+    pub graduation: Option<String>,
+}
+
+impl Default for AttributeMap {
+    fn default() -> Self {
+        AttributeMap {
+            search: "uid".to_owned(),
+            eid: "utexasEduPersonEid".to_owned(),
+            name: "displayName".to_owned(),
+            email: "mail".to_owned(),
+            major: "utexasEduPersonMajor".to_owned(),
+            school: "utexasEduPersonSchool".to_owned(),
+            affiliation: "utexasEduPersonPubAffiliation".to_owned(),
+            classification: Some("utexasEduPersonClassification".to_owned()),
+            department: Some("ou".to_owned()),
+            title: Some("title".to_owned()),
+            graduation: Some("utexasEduPersonGraduationTerm".to_owned()),
+        }
+    }
+}
+
+impl AttributeMap {
+    /// Every mapped attribute, to request in searches so that nothing else is returned.
+    pub fn attributes(&self) -> Vec<&str> {
+        let required = [&self.eid, &self.name, &self.email, &self.major, &self.school, &self.affiliation];
+        let optional = [&self.classification, &self.department, &self.title, &self.graduation];
+
+        required
+            .into_iter()
+            .chain(optional.into_iter().flatten())
+            .map(String::as_str)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn overrides_only_the_given_attributes() {
+        let map: AttributeMap = serde_json::from_str(r#"{ "eid": "uid", "title": null }"#).unwrap();
+        assert_eq!(map.eid, "uid");
+        assert_eq!(map.major, "utexasEduPersonMajor");
+        assert_eq!(map.title, None);
+        assert_eq!(map.department.as_deref(), Some("ou"));
+
+        assert!(serde_json::from_str::<AttributeMap>(r#"{ "majr": "major" }"#).is_err());
+    }
+
+    #[test]
+    fn requests_every_mapped_attribute() {
+        let map = AttributeMap::default();
+        let attributes = map.attributes();
+        assert!(attributes.contains(&"utexasEduPersonEid"));
+        assert!(attributes.contains(&"utexasEduPersonClassification"));
+        assert_eq!(attributes.len(), 10);
+
+        let map = AttributeMap { classification: None, graduation: None, ..AttributeMap::default() };
+        assert_eq!(map.attributes().len(), 8);
+        assert!(!map.attributes().contains(&"utexasEduPersonClassification"));
+    }
+}
@@ -0,0 +1,178 @@
+//! Short one-time codes, emailed instead of a token link when a request asks for one.
+//!
+//! The claims stay on the server until the code is redeemed for the same account, at
+//! which point they are returned unchanged, ready to be encoded as a regular token.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use rand::Rng;
+use utv_token::{Clock, VerifiedClaims};
+
+/// Crockford's base32, which leaves out I, L, O and U so codes are hard to misread.
+const ALPHABET: &[u8] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+pub const CODE_LENGTH: usize = 8;
+/// Wrong guesses allowed before the code is thrown away.
+pub const MAX_ATTEMPTS: u32 = 5;
+
+struct PendingCode {
+    code: String,
+    claims: VerifiedClaims,
+    attempts: u32,
+}
+
+/// Codes waiting to be redeemed, keyed by the `(service, service_id)` they were issued for.
+#[derive(Default)]
+pub struct PendingCodes {
+    pending: Mutex<HashMap<(String, String), PendingCode>>,
+}
+
+impl PendingCodes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Generates a code for `claims`, replacing any code previously issued for the same account.
+    pub fn issue(&self, claims: VerifiedClaims) -> String {
+        let mut rng = rand::thread_rng();
+        let code: String = (0..CODE_LENGTH)
+            .map(|_| ALPHABET[rng.gen_range(0..ALPHABET.len())] as char)
+            .collect();
+
+        let account = (claims.service.clone(), claims.service_id.clone());
+        let pending = PendingCode { code: code.clone(), claims, attempts: 0 };
+        self.pending.lock().unwrap().insert(account, pending);
+        code
+    }
+
+    /// Checks `code` for the given account, giving back the claims it was issued with.
+    ///
+    /// Codes are single-use, and are discarded once they expire or after `MAX_ATTEMPTS` wrong guesses.
+    pub fn redeem(
+        &self,
+        service: &str,
+        service_id: &str,
+        code: &str,
+        clock: &dyn Clock,
+    ) -> Result<VerifiedClaims, RedeemError> {
+        let mut pending = self.pending.lock().unwrap();
+        let account = (service.to_owned(), service_id.to_owned());
+        let entry = pending.get_mut(&account).ok_or(RedeemError::NoPendingCode)?;
+
+        if clock.now() >= entry.claims.exp {
+            pending.remove(&account);
+            return Err(RedeemError::Expired);
+        }
+
+        let matches = ring::constant_time::verify_slices_are_equal(normalize(code).as_bytes(), entry.code.as_bytes()).is_ok();
+        if !matches {
+            entry.attempts += 1;
+            if entry.attempts >= MAX_ATTEMPTS {
+                pending.remove(&account);
+                return Err(RedeemError::TooManyAttempts);
+            }
+            return Err(RedeemError::WrongCode);
+        }
+
+        Ok(pending.remove(&account).unwrap().claims)
+    }
+
+    /// Drops codes that expired before `now`.
+    pub fn purge_expired(&self, now: u64) {
+        self.pending.lock().unwrap().retain(|_, entry| entry.claims.exp > now);
+    }
+}
+
+/// Undoes what people tend to do when typing a code: lowercase, spaces and dashes, and
+/// letters that look like digits.
+fn normalize(code: &str) -> String {
+    code.chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .map(|c| match c.to_ascii_uppercase() {
+            'O' => '0',
+            'I' | 'L' => '1',
+            c => c,
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+pub enum RedeemError {
+    NoPendingCode,
+    WrongCode,
+    TooManyAttempts,
+    Expired,
+}
+
+#[cfg(test)]
+mod test {
+    use utv_token::{Affiliation, SystemClock};
+
+    use super::*;
+    use crate::directory::Person;
+
+    struct FixedClock(u64);
+
+    impl Clock for FixedClock {
+        fn now(&self) -> u64 {
+            self.0
+        }
+    }
+
+    fn claims() -> VerifiedClaims {
+        let person = Person {
+            encrypted_eid: vec![1, 2, 3],
+            major: vec![],
+            school: vec!["College of Natural Sciences".to_owned()],
+            affiliation: vec![Affiliation::Student],
+            classification: vec![],
+            department: vec![],
+            title: vec![],
+            graduation: vec![],
+            name: "Alex Longhorn".to_owned(),
+            email: None,
+        };
+        person.claims("utexas", "discord", "1234")
+    }
+
+    #[test]
+    fn redeems_each_code_once() {
+        let codes = PendingCodes::new();
+        let code = codes.issue(claims());
+        assert_eq!(code.len(), CODE_LENGTH);
+
+        assert!(matches!(codes.redeem("discord", "5678", &code, &SystemClock), Err(RedeemError::NoPendingCode)));
+        let typed = format!("{}-{}", &code[..4], code[4..].to_lowercase());
+        assert_eq!(codes.redeem("discord", "1234", &typed, &SystemClock).unwrap().encrypted_eid, vec![1, 2, 3]);
+        assert!(matches!(codes.redeem("discord", "1234", &code, &SystemClock), Err(RedeemError::NoPendingCode)));
+    }
+
+    #[test]
+    fn gives_up_after_too_many_attempts() {
+        let codes = PendingCodes::new();
+        let code = codes.issue(claims());
+        let wrong = if code == "00000000" { "11111111" } else { "00000000" };
+
+        for _ in 1..MAX_ATTEMPTS {
+            assert!(matches!(codes.redeem("discord", "1234", wrong, &SystemClock), Err(RedeemError::WrongCode)));
+        }
+        assert!(matches!(codes.redeem("discord", "1234", wrong, &SystemClock), Err(RedeemError::TooManyAttempts)));
+        assert!(matches!(codes.redeem("discord", "1234", &code, &SystemClock), Err(RedeemError::NoPendingCode)));
+    }
+
+    #[test]
+    fn refuses_expired_codes() {
+        let codes = PendingCodes::new();
+        let issued = claims();
+        let exp = issued.exp;
+        let code = codes.issue(issued);
+
+        // even the right code is refused once expired, and is then gone
+        assert!(matches!(codes.redeem("discord", "1234", &code, &FixedClock(exp)), Err(RedeemError::Expired)));
+        assert!(matches!(codes.redeem("discord", "1234", &code, &FixedClock(exp - 1)), Err(RedeemError::NoPendingCode)));
+
+        let code = codes.issue(claims());
+        codes.purge_expired(exp + 60);
+        assert!(matches!(codes.redeem("discord", "1234", &code, &SystemClock), Err(RedeemError::NoPendingCode)));
+    }
+}
@@ -1,74 +1,282 @@
-use super::deterministic_aes;
+use std::collections::HashMap;
+
+use async_trait::async_trait;
 use ldap3::{Scope, SearchEntry};
-use utv_token::VerifiedClaims;
+use serde::Deserialize;
 
-#[derive(Debug)]
+use super::deterministic_aes;
+use crate::attributes::AttributeMap;
+use crate::eid::{Eid, IdSyntax};
+use crate::ldap_pool::LdapPool;
+use utv_token::{Affiliation, Clock, SystemClock, VerifiedClaims};
+
+#[derive(Debug, Clone)]
 pub struct Person {
-    pub claims: VerifiedClaims,
+    pub encrypted_eid: Vec<u8>,
+    pub major: Vec<String>,
+    pub school: Vec<String>,
+    pub affiliation: Vec<Affiliation>,
+    pub classification: Vec<String>,
+    pub department: Vec<String>,
+    pub title: Vec<String>,
+    pub graduation: Vec<String>,
     pub name: String,
     pub email: Option<String>,
 }
 
+/// Fields that a person can be refused for missing from the directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DirectoryField {
+    Name,
+    Affiliation,
+    School,
+}
+
+const ALWAYS_REQUIRED: &[DirectoryField] = &[DirectoryField::Name, DirectoryField::Affiliation];
+
+/// Directory fields a person with `affiliation` must have, besides a name and an affiliation.
+///
+/// Faculty, staff and many graduate students have no major, so only a student's school is
+/// required, and everything else is carried as an empty list when missing.
+fn required_fields(affiliation: &Affiliation) -> &'static [DirectoryField] {
+    match affiliation {
+        Affiliation::Student => &[DirectoryField::School],
+        Affiliation::Faculty | Affiliation::Staff | Affiliation::Affiliate | Affiliation::Retiree | Affiliation::Other(_) => &[],
+    }
+}
 
 impl Person {
-    pub async fn lookup(ldap: &mut ldap3::Ldap, eid: &str, encryption_key: &[u8]) -> Result<Person, LookupError> {
-        let results = ldap
+    fn has(&self, field: DirectoryField) -> bool {
+        match field {
+            DirectoryField::Name => !self.name.is_empty(),
+            DirectoryField::Affiliation => !self.affiliation.is_empty(),
+            DirectoryField::School => !self.school.is_empty(),
+        }
+    }
+
+    /// Checks that the person has a name, an affiliation, and the fields required by each
+    /// of their affiliations.
+    fn check_required(self) -> Result<Self, LookupError> {
+        let missing = ALWAYS_REQUIRED
+            .iter()
+            .chain(self.affiliation.iter().flat_map(required_fields))
+            .copied()
+            .find(|&field| !self.has(field));
+
+        match missing {
+            Some(field) => Err(LookupError::MissingDirectoryInfo(field)),
+            None => Ok(self),
+        }
+    }
+}
+
+/// Where people are looked up by EID.
+#[async_trait]
+pub trait DirectoryBackend: Send + Sync {
+    async fn lookup(&self, eid: &Eid) -> Result<Person, LookupError>;
+}
+
+/// An institution's LDAP directory, over pooled connections.
+pub struct LdapDirectory {
+    pool: LdapPool,
+    base_dn: String,
+    attributes: AttributeMap,
+    encryption_key: Vec<u8>,
+}
+
+impl LdapDirectory {
+    pub fn new(pool: LdapPool, base_dn: &str, attributes: AttributeMap, encryption_key: &[u8]) -> Self {
+        LdapDirectory {
+            pool,
+            base_dn: base_dn.to_owned(),
+            attributes,
+            encryption_key: encryption_key.to_vec(),
+        }
+    }
+}
+
+#[async_trait]
+impl DirectoryBackend for LdapDirectory {
+    async fn lookup(&self, eid: &Eid) -> Result<Person, LookupError> {
+        let mut ldap = self.pool.get().await?;
+        let results = match ldap
             .search(
-                "dc=directory,dc=utexas,dc=edu",
+                &self.base_dn,
                 Scope::Subtree,
-                &format!("uid={}", eid),
-                vec!["*"],
+                &format!("({}={})", self.attributes.search, eid.filter_value()),
+                self.attributes.attributes(),
             )
-            .await?;
+            .await
+        {
+            Ok(results) => results,
+            Err(err) => {
+                // the connection may be broken, so don't hand it out again
+                ldap.discard();
+                return Err(err.into());
+            }
+        };
+        drop(ldap);
 
-        let mut entry = results
+        let entry = results
             .0
             .into_iter()
-            .map(|r| SearchEntry::construct(r))
-            .find(|e| match e.attrs.get("utexasEduPersonEid") {
-                Some(eids) => eids[0] == eid,
+            .map(SearchEntry::construct)
+            .find(|e| match e.attrs.get(&self.attributes.eid) {
+                Some(eids) => matches!(eids.first(), Some(found) if found.eq_ignore_ascii_case(eid.as_str())),
                 _ => false,
             })
             .ok_or(LookupError::NotFound)?;
 
-        let encrypted_eid = deterministic_aes::encrypt(eid.as_bytes(), encryption_key);
+        self.person(eid, entry)
+    }
+}
+
+impl LdapDirectory {
+    /// Reads a person from their directory entry, through the attribute map.
+    fn person(&self, eid: &Eid, mut entry: SearchEntry) -> Result<Person, LookupError> {
+        let encrypted_eid = deterministic_aes::encrypt(eid.as_str().as_bytes(), &self.encryption_key);
 
-        let claims = VerifiedClaims {
+        let map = &self.attributes;
+        let person = Person {
             encrypted_eid,
-            major: entry
-                .attrs
-                .remove("utexasEduPersonMajor")
-                .ok_or(LookupError::MissingDirectoryInfo("major"))?,
-            school: entry
-                .attrs
-                .remove("utexasEduPersonSchool")
-                .ok_or(LookupError::MissingDirectoryInfo("school"))?,
-            affiliation: entry
-                .attrs
-                .remove("utexasEduPersonPubAffiliation")
-                .ok_or(LookupError::MissingDirectoryInfo("affiliation"))?,
+            major: take_values(&mut entry, Some(&map.major)),
+            school: take_values(&mut entry, Some(&map.school)),
+            affiliation: take_values(&mut entry, Some(&map.affiliation))
+                .iter()
+                .map(|a| Affiliation::from(a.as_str()))
+                .collect(),
+            classification: take_values(&mut entry, map.classification.as_ref()),
+            department: take_values(&mut entry, map.department.as_ref()),
+            title: take_values(&mut entry, map.title.as_ref()),
+            graduation: take_values(&mut entry, map.graduation.as_ref()),
+            name: take_values(&mut entry, Some(&map.name))
+                .into_iter()
+                .next()
+                .ok_or(LookupError::MissingDirectoryInfo(DirectoryField::Name))?,
+            email: take_values(&mut entry, Some(&map.email)).into_iter().next(),
         };
 
+        person.check_required()
+    }
+}
+
+/// Values of `attribute` in `entry`, or none if it is missing or not mapped.
+fn take_values(entry: &mut SearchEntry, attribute: Option<&String>) -> Vec<String> {
+    attribute.and_then(|attribute| entry.attrs.remove(attribute)).unwrap_or_default()
+}
+
+/// A person as listed in a directory fixture.
+#[derive(Deserialize, Debug, Clone)]
+pub struct DirectoryEntry {
+    #[serde(default)]
+    pub major: Vec<String>,
+    #[serde(default)]
+    pub school: Vec<String>,
+    #[serde(default)]
+    pub affiliation: Vec<Affiliation>,
+    #[serde(default)]
+    pub classification: Vec<String>,
+    #[serde(default)]
+    pub department: Vec<String>,
+    #[serde(default)]
+    pub title: Vec<String>,
+    #[serde(default)]
+    pub graduation: Vec<String>,
+    pub name: String,
+    pub email: Option<String>,
+}
+
+/// A fixed set of people, for tests and local development without directory access.
+pub struct MemoryDirectory {
+    entries: HashMap<Eid, DirectoryEntry>,
+    encryption_key: Vec<u8>,
+}
+
+impl MemoryDirectory {
+    #[cfg(test)]
+    pub fn new(encryption_key: &[u8]) -> Self {
+        MemoryDirectory {
+            entries: HashMap::new(),
+            encryption_key: encryption_key.to_vec(),
+        }
+    }
+
+    /// Loads a fixture file, a JSON object mapping each EID to a [`DirectoryEntry`].
+    ///
+    /// EIDs are checked against `syntax` and normalized like any other, so the file may use
+    /// any casing.
+    pub fn from_json(json: &str, syntax: &IdSyntax, encryption_key: &[u8]) -> serde_json::Result<Self> {
+        let entries: HashMap<String, DirectoryEntry> = serde_json::from_str(json)?;
+        let entries = entries
+            .into_iter()
+            .map(|(eid, entry)| match Eid::parse(&eid, &[], syntax) {
+                Ok(eid) => Ok((eid, entry)),
+                Err(err) => Err(serde::de::Error::custom(format!("{:?} is {}", eid, err))),
+            })
+            .collect::<serde_json::Result<_>>()?;
+
+        Ok(MemoryDirectory {
+            entries,
+            encryption_key: encryption_key.to_vec(),
+        })
+    }
+
+    #[cfg(test)]
+    pub fn insert(&mut self, eid: Eid, entry: DirectoryEntry) -> &mut Self {
+        self.entries.insert(eid, entry);
+        self
+    }
+}
+
+#[async_trait]
+impl DirectoryBackend for MemoryDirectory {
+    async fn lookup(&self, eid: &Eid) -> Result<Person, LookupError> {
+        let entry = self.entries.get(eid).cloned().ok_or(LookupError::NotFound)?;
         let person = Person {
-            claims,
-            name: entry
-                .attrs
-                .remove("displayName")
-                .and_then(|a| a.into_iter().next())
-                .ok_or(LookupError::MissingDirectoryInfo("name"))?,
-            email: entry
-                .attrs
-                .remove("mail")
-                .and_then(|a| a.into_iter().next()),
+            encrypted_eid: deterministic_aes::encrypt(eid.as_str().as_bytes(), &self.encryption_key),
+            major: entry.major,
+            school: entry.school,
+            affiliation: entry.affiliation,
+            classification: entry.classification,
+            department: entry.department,
+            title: entry.title,
+            graduation: entry.graduation,
+            name: entry.name,
+            email: entry.email,
         };
 
-        Ok(person)
+        person.check_required()
+    }
+}
+
+impl Person {
+    /// Claims for a new single-use token, valid from now, that can only be redeemed for the given account.
+    pub fn claims(&self, institution: &str, service: &str, service_id: &str) -> VerifiedClaims {
+        let iat = SystemClock.now();
+
+        VerifiedClaims {
+            encrypted_eid: self.encrypted_eid.clone(),
+            major: self.major.clone(),
+            school: self.school.clone(),
+            affiliation: self.affiliation.clone(),
+            iat,
+            exp: iat + utv_token::TOKEN_LIFETIME.as_secs(),
+            service: service.to_owned(),
+            service_id: service_id.to_owned(),
+            jti: utv_token::new_token_id(),
+            classification: self.classification.clone(),
+            department: self.department.clone(),
+            title: self.title.clone(),
+            graduation: self.graduation.clone(),
+            institution: institution.to_owned(),
+            ..Default::default()
+        }
     }
 }
 
 #[derive(Debug)]
 pub enum LookupError {
-    MissingDirectoryInfo(&'static str),
+    MissingDirectoryInfo(DirectoryField),
     NotFound,
     LdapError(ldap3::LdapError),
 }
@@ -78,3 +286,84 @@ impl From<ldap3::LdapError> for LookupError {
         Self::LdapError(err)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reads_mapped_attributes_from_ldap_entries() {
+        let key: [u8; 32] = rand::random();
+        let attrs = [
+            ("utexasEduPersonEid", "abc123"),
+            ("displayName", "Alex Longhorn"),
+            ("utexasEduPersonPubAffiliation", "student"),
+            ("utexasEduPersonSchool", "College of Natural Sciences"),
+            ("utexasEduPersonClassification", "Senior"),
+            ("utexasEduPersonGraduationTerm", "Spring 2025"),
+            ("title", "Student Associate"),
+            ("jobCode", "1234"),
+        ];
+        let entry = || SearchEntry {
+            dn: "uid=abc123,dc=directory,dc=utexas,dc=edu".to_owned(),
+            attrs: attrs.iter().map(|&(k, v)| (k.to_owned(), vec![v.to_owned()])).collect(),
+            bin_attrs: HashMap::new(),
+        };
+        let eid = Eid::ut("abc123");
+
+        // the pool connects lazily, so no directory is needed
+        let directory = LdapDirectory::new(LdapPool::new("ldap://localhost", 1), "", AttributeMap::default(), &key);
+        let person = directory.person(&eid, entry()).unwrap();
+        assert_eq!(person.classification, vec!["Senior"]);
+        assert_eq!(person.graduation, vec!["Spring 2025"]);
+        assert_eq!(person.title, vec!["Student Associate"]);
+        assert!(person.department.is_empty() && person.major.is_empty());
+
+        let unmapped = AttributeMap { classification: None, ..AttributeMap::default() };
+        let directory = LdapDirectory::new(LdapPool::new("ldap://localhost", 1), "", unmapped, &key);
+        assert!(directory.person(&eid, entry()).unwrap().classification.is_empty());
+    }
+
+    #[tokio::test]
+    async fn looks_up_fixture_entries() {
+        let key: [u8; 32] = rand::random();
+        let fixture = r#"{
+            "ABC123": {
+                "major": ["Computer Science"],
+                "school": ["College of Natural Sciences"],
+                "affiliation": ["student"],
+                "name": "Alex Longhorn"
+            },
+            "prof1": {
+                "school": ["Cockrell School of Engineering"],
+                "affiliation": ["faculty"],
+                "name": "Pat Professor"
+            },
+            "noschool": {
+                "affiliation": ["student"],
+                "name": "Sam Student"
+            },
+            "nobody": {
+                "name": "Nobody"
+            }
+        }"#;
+        let directory = MemoryDirectory::from_json(fixture, &IdSyntax::default(), &key).unwrap();
+
+        let person = directory.lookup(&Eid::ut("abc123@utexas.edu")).await.unwrap();
+        assert_eq!(person.affiliation, vec![Affiliation::Student]);
+        assert_eq!(deterministic_aes::decrypt(&person.encrypted_eid, &key).unwrap(), b"abc123");
+        assert!(matches!(directory.lookup(&Eid::ut("zz999")).await, Err(LookupError::NotFound)));
+
+        // faculty need no major, but students still need a school
+        let faculty = directory.lookup(&Eid::ut("prof1")).await.unwrap();
+        assert!(faculty.major.is_empty());
+        assert!(matches!(
+            directory.lookup(&Eid::ut("noschool")).await,
+            Err(LookupError::MissingDirectoryInfo(DirectoryField::School))
+        ));
+        assert!(matches!(
+            directory.lookup(&Eid::ut("nobody")).await,
+            Err(LookupError::MissingDirectoryInfo(DirectoryField::Affiliation))
+        ));
+    }
+}
@@ -0,0 +1,145 @@
+//! Caches directory lookups, so that repeated requests for the same EID don't each search
+//! the directory.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+
+use crate::directory::{DirectoryBackend, LookupError, Person};
+use crate::eid::Eid;
+
+/// How long people who were found are remembered.
+pub const FOUND_TTL: Duration = Duration::from_secs(10 * 60);
+/// How long EIDs that don't exist are remembered, which is shorter in case they were just created.
+pub const NOT_FOUND_TTL: Duration = Duration::from_secs(60);
+
+struct Cached {
+    /// `None` if the EID was not found
+    person: Option<Person>,
+    expires: Instant,
+}
+
+/// A [`DirectoryBackend`] that remembers the results of another for a while.
+///
+/// Only found people and `NotFound` are cached; other errors are retried every time.
+pub struct CachedDirectory<D> {
+    inner: D,
+    capacity: usize,
+    entries: Mutex<HashMap<Eid, Cached>>,
+}
+
+impl<D: DirectoryBackend> CachedDirectory<D> {
+    /// Caches lookups in `inner`, remembering at most `capacity` EIDs.
+    pub fn new(inner: D, capacity: usize) -> Self {
+        CachedDirectory {
+            inner,
+            capacity,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn insert(&self, eid: &Eid, person: Option<Person>, ttl: Duration) {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+
+        if entries.len() >= self.capacity && !entries.contains_key(eid) {
+            entries.retain(|_, cached| cached.expires > now);
+        }
+        if entries.len() >= self.capacity && !entries.contains_key(eid) {
+            let soonest = entries.iter().min_by_key(|(_, cached)| cached.expires).map(|(eid, _)| eid.clone());
+            if let Some(soonest) = soonest {
+                entries.remove(&soonest);
+            }
+        }
+
+        entries.insert(eid.clone(), Cached { person, expires: now + ttl });
+    }
+}
+
+#[async_trait]
+impl<D: DirectoryBackend> DirectoryBackend for CachedDirectory<D> {
+    async fn lookup(&self, eid: &Eid) -> Result<Person, LookupError> {
+        let cached = self
+            .entries
+            .lock()
+            .unwrap()
+            .get(eid)
+            .filter(|cached| cached.expires > Instant::now())
+            .map(|cached| cached.person.clone());
+        match cached {
+            Some(Some(person)) => return Ok(person),
+            Some(None) => return Err(LookupError::NotFound),
+            None => {}
+        }
+
+        match self.inner.lookup(eid).await {
+            Ok(person) => {
+                self.insert(eid, Some(person.clone()), FOUND_TTL);
+                Ok(person)
+            }
+            Err(LookupError::NotFound) => {
+                self.insert(eid, None, NOT_FOUND_TTL);
+                Err(LookupError::NotFound)
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::directory::{DirectoryEntry, MemoryDirectory};
+
+    struct Counting(MemoryDirectory, AtomicUsize);
+
+    #[async_trait]
+    impl DirectoryBackend for Counting {
+        async fn lookup(&self, eid: &Eid) -> Result<Person, LookupError> {
+            self.1.fetch_add(1, Ordering::SeqCst);
+            self.0.lookup(eid).await
+        }
+    }
+
+    #[tokio::test]
+    async fn caches_found_and_missing_eids() {
+        let mut memory = MemoryDirectory::new(&[0; 32]);
+        for eid in ["abc123", "def456"] {
+            memory.insert(
+                Eid::ut(eid),
+                DirectoryEntry {
+                    major: vec![],
+                    school: vec![],
+                    affiliation: vec![utv_token::Affiliation::Staff],
+                    classification: vec![],
+                    department: vec![],
+                    title: vec![],
+                    graduation: vec![],
+                    name: eid.to_owned(),
+                    email: None,
+                },
+            );
+        }
+        let directory = CachedDirectory::new(Counting(memory, AtomicUsize::new(0)), 2);
+        let lookups = || directory.inner.1.load(Ordering::SeqCst);
+
+        let abc = Eid::ut("abc123");
+        assert_eq!(directory.lookup(&abc).await.unwrap().name, "abc123");
+        assert!(directory.lookup(&abc).await.is_ok());
+        assert_eq!(lookups(), 1);
+
+        let missing = Eid::ut("zz999");
+        assert!(matches!(directory.lookup(&missing).await, Err(LookupError::NotFound)));
+        assert!(matches!(directory.lookup(&missing).await, Err(LookupError::NotFound)));
+        assert_eq!(lookups(), 2);
+
+        // over capacity, the entry closest to expiring makes room
+        assert!(directory.lookup(&Eid::ut("def456")).await.is_ok());
+        assert!(directory.lookup(&missing).await.is_err());
+        assert_eq!(lookups(), 4);
+    }
+}
@@ -0,0 +1,142 @@
+//! EIDs, and the equivalent ids at other institutions, as typed by students and looked up
+//! in the directory.
+
+use std::fmt;
+
+use serde::Deserialize;
+
+/// What an institution's ids look like, set as the `id_syntax` of its profile.
+///
+/// Ids are always lowercased ASCII letters and digits, plus any of `punctuation`. The
+/// default is UT's EID syntax, e.g. `bha366`.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct IdSyntax {
+    pub min_length: usize,
+    pub max_length: usize,
+    /// Whether ids may start with a digit, rather than only a letter
+    pub leading_digit: bool,
+    /// Characters other than letters and digits that ids may contain, e.g. `.-_`
+    pub punctuation: String,
+}
+
+impl Default for IdSyntax {
+    fn default() -> Self {
+        IdSyntax {
+            min_length: 2,
+            max_length: 8,
+            leading_digit: false,
+            punctuation: String::new(),
+        }
+    }
+}
+
+impl IdSyntax {
+    fn allows(&self, id: &str) -> bool {
+        let mut chars = id.chars();
+        id.len() >= self.min_length
+            && id.len() <= self.max_length
+            && matches!(chars.next(), Some(c) if c.is_ascii_lowercase() || (self.leading_digit && c.is_ascii_digit()))
+            && chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || self.punctuation.contains(c))
+    }
+}
+
+/// A syntactically valid EID, lowercased and without any email domain.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Eid(String);
+
+impl Eid {
+    /// Normalizes and checks an id in `syntax`, stripping any of the email `domains` following
+    /// it, so that forms like ` BHA366@utexas.edu` are accepted.
+    pub fn parse(eid: &str, domains: &[&str], syntax: &IdSyntax) -> Result<Self, InvalidEid> {
+        let mut eid = eid.trim().to_ascii_lowercase();
+        if let Some((id, domain)) = eid.split_once('@') {
+            if domains.iter().any(|d| d.eq_ignore_ascii_case(domain)) {
+                eid = id.to_owned();
+            }
+        }
+
+        if !syntax.allows(&eid) {
+            return Err(InvalidEid);
+        }
+
+        Ok(Eid(eid))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The EID escaped for use as a value in an LDAP search filter, per RFC 4515.
+    ///
+    /// Parsing only allows the punctuation an institution's profile lists, which may include
+    /// special characters, so this must always be used when building a filter.
+    pub fn filter_value(&self) -> std::borrow::Cow<'_, str> {
+        ldap3::ldap_escape(&self.0)
+    }
+}
+
+#[cfg(test)]
+impl Eid {
+    /// Parses a UT EID, for tests.
+    pub fn ut(eid: &str) -> Self {
+        crate::institution::InstitutionProfile::utexas().parse_eid(eid).unwrap()
+    }
+}
+
+impl fmt::Display for Eid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidEid;
+
+impl fmt::Display for InvalidEid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "not a valid EID")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const UT: &[&str] = &["utexas.edu", "eid.utexas.edu"];
+
+    #[test]
+    fn normalizes_eids() {
+        let syntax = IdSyntax::default();
+        assert_eq!(Eid::parse(" BHA366@utexas.edu ", UT, &syntax).unwrap().as_str(), "bha366");
+        assert_eq!(Eid::parse("bha366@eid.utexas.edu", UT, &syntax).unwrap().as_str(), "bha366");
+        assert_eq!(Eid::parse("ab12345", UT, &syntax).unwrap().as_str(), "ab12345");
+        assert_eq!(Eid::parse("abc12@txstate.edu", &["txstate.edu"], &syntax).unwrap().as_str(), "abc12");
+        assert!(Eid::parse("abc12@utexas.edu", &["txstate.edu"], &syntax).is_err());
+    }
+
+    #[test]
+    fn rejects_filter_syntax() {
+        for eid in ["*", "bha366)(uid=*", "bha366*", "", "366bha", "bha366@gmail.com", "toolongeid1"] {
+            assert!(Eid::parse(eid, UT, &IdSyntax::default()).is_err(), "{} was accepted", eid);
+        }
+    }
+
+    #[test]
+    fn follows_other_institutions_syntax() {
+        let netid = IdSyntax {
+            min_length: 3,
+            max_length: 16,
+            leading_digit: true,
+            punctuation: ".".to_owned(),
+        };
+        assert_eq!(Eid::parse("Jane.Doe42", &[], &netid).unwrap().as_str(), "jane.doe42");
+        assert!(Eid::parse("1abc", &[], &netid).is_ok());
+        assert!(Eid::parse("1abc", &[], &IdSyntax::default()).is_err());
+        assert!(Eid::parse("ab", &[], &netid).is_err());
+        assert!(Eid::parse("jane_doe", &[], &netid).is_err());
+
+        let escaped = Eid::parse("a*b", &[], &IdSyntax { punctuation: "*".to_owned(), ..IdSyntax::default() }).unwrap();
+        assert_eq!(escaped.filter_value(), "a\\2ab");
+    }
+}
@@ -0,0 +1,232 @@
+//! Schools whose students can verify, each with its own directory, schema and branding.
+//!
+//! Loaded from the JSON array of profiles in `INSTITUTIONS` when set, otherwise UT Austin
+//! is the only institution. Each profile needs a unique `id` and says where its directory
+//! and mail are, while `attributes`, `id_syntax` and `branding` default to UT's, e.g.
+//!
+//! ```json
+//! [{
+//!     "id": "txstate",
+//!     "directory_url": "ldap://ldap.txstate.edu:389",
+//!     "base_dn": "dc=txstate,dc=edu",
+//!     "attributes": { "eid": "uid", "major": "major", "school": "college", "affiliation": "eduPersonAffiliation" },
+//!     "id_syntax": { "max_length": 16, "leading_digit": true },
+//!     "email_domain": "txstate.edu",
+//!     "email_aliases": [],
+//!     "branding": { "id_label": "NetID" }
+//! }]
+//! ```
+
+use std::collections::HashSet;
+use std::fmt;
+
+use ring::hmac;
+use serde::Deserialize;
+
+use crate::attributes::AttributeMap;
+use crate::eid::{Eid, IdSyntax, InvalidEid};
+
+/// Institution that requests without one are for, and that unlabelled tokens were issued by.
+pub const DEFAULT_INSTITUTION: &str = "utexas";
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Branding {
+    /// Name the emails are sent under
+    pub product: String,
+    /// What the institution calls the id students verify with
+    pub id_label: String,
+    pub support_email: String,
+    /// Page the emailed token is appended to, after a `#`
+    pub verify_url: String,
+    /// Page where emailed codes are entered and sent to `POST /redeem`. Requests for a code
+    /// get a link instead until this is set.
+    pub redeem_url: Option<String>,
+}
+
+impl Default for Branding {
+    fn default() -> Self {
+        Branding {
+            product: "Discord VerifiedBot".to_owned(),
+            id_label: "UT EID".to_owned(),
+            support_email: "support@verifiedbot.com".to_owned(),
+            verify_url: "https://verifiedbot.com/verify".to_owned(),
+            redeem_url: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct InstitutionProfile {
+    /// Recorded in the `institution` claim of every token issued for this institution
+    pub id: String,
+    pub directory_url: String,
+    pub base_dn: String,
+    #[serde(default)]
+    pub attributes: AttributeMap,
+    /// What ids typed by students must look like
+    #[serde(default)]
+    pub id_syntax: IdSyntax,
+    /// Verification emails go to `id@email_domain`
+    pub email_domain: String,
+    /// Other domains students may type after their id, which are stripped
+    pub email_aliases: Vec<String>,
+    #[serde(default)]
+    pub branding: Branding,
+}
+
+impl InstitutionProfile {
+    /// UT Austin's profile, used when `INSTITUTIONS` is unset.
+    pub fn utexas() -> Self {
+        InstitutionProfile {
+            id: DEFAULT_INSTITUTION.to_owned(),
+            directory_url: "ldap://directory.utexas.edu:389".to_owned(),
+            base_dn: "dc=directory,dc=utexas,dc=edu".to_owned(),
+            attributes: AttributeMap::default(),
+            id_syntax: IdSyntax::default(),
+            email_domain: "eid.utexas.edu".to_owned(),
+            email_aliases: vec!["utexas.edu".to_owned(), "austin.utexas.edu".to_owned()],
+            branding: Branding::default(),
+        }
+    }
+
+    /// Loads the profiles in `INSTITUTIONS`, or only UT Austin's when it is unset.
+    pub fn load_all() -> Vec<InstitutionProfile> {
+        match std::env::var("INSTITUTIONS") {
+            Ok(path) => {
+                let json = std::fs::read_to_string(path).expect("Invalid INSTITUTIONS");
+                Self::parse_all(&json).unwrap_or_else(|err| panic!("Invalid INSTITUTIONS: {}", err))
+            }
+            Err(_) => vec![InstitutionProfile::utexas()],
+        }
+    }
+
+    /// Parses a JSON array of profiles, each with a distinct id.
+    pub fn parse_all(json: &str) -> Result<Vec<InstitutionProfile>, InvalidInstitutions> {
+        let profiles: Vec<InstitutionProfile> = serde_json::from_str(json).map_err(InvalidInstitutions::Json)?;
+
+        let mut ids = HashSet::new();
+        for profile in &profiles {
+            if !ids.insert(profile.id.as_str()) {
+                return Err(InvalidInstitutions::DuplicateId(profile.id.clone()));
+            }
+        }
+
+        Ok(profiles)
+    }
+
+    /// Domains that may follow an id typed by a student.
+    pub fn domains(&self) -> Vec<&str> {
+        std::iter::once(&self.email_domain).chain(&self.email_aliases).map(String::as_str).collect()
+    }
+
+    /// Normalizes and checks an id typed by a student, see [`Eid::parse`].
+    pub fn parse_eid(&self, eid: &str) -> Result<Eid, InvalidEid> {
+        Eid::parse(eid, &self.domains(), &self.id_syntax)
+    }
+
+    /// Key that this institution's EIDs are encrypted with.
+    ///
+    /// UT keeps using `encryption_key` itself, so that existing users keep their encrypted
+    /// EIDs, while other institutions get a key derived from it, so that the same id at two
+    /// schools isn't mistaken for one person.
+    pub fn encryption_key(&self, encryption_key: &[u8]) -> Vec<u8> {
+        if self.id == DEFAULT_INSTITUTION {
+            return encryption_key.to_vec();
+        }
+        let key = hmac::Key::new(hmac::HMAC_SHA256, encryption_key);
+        hmac::sign(&key, self.id.as_bytes()).as_ref().to_vec()
+    }
+}
+
+#[derive(Debug)]
+pub enum InvalidInstitutions {
+    Json(serde_json::Error),
+    DuplicateId(String),
+}
+
+impl fmt::Display for InvalidInstitutions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvalidInstitutions::Json(err) => write!(f, "{}", err),
+            InvalidInstitutions::DuplicateId(id) => write!(f, "more than one profile has id {:?}", id),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A profile for `id` with only the fields every profile needs.
+    fn profile(id: &str) -> String {
+        format!(
+            r#"{{ "id": "{0}", "directory_url": "ldap://ldap.{0}.edu:389", "base_dn": "dc={0},dc=edu", "email_domain": "{0}.edu", "email_aliases": [] }}"#,
+            id
+        )
+    }
+
+    #[test]
+    fn parses_profiles_with_ut_schema_and_branding() {
+        let profiles = InstitutionProfile::parse_all(&format!("[{}]", profile("txstate"))).unwrap();
+        let txstate = &profiles[0];
+        assert_eq!(txstate.domains(), vec!["txstate.edu"]);
+        assert_eq!(txstate.base_dn, "dc=txstate,dc=edu");
+        assert_eq!(txstate.attributes.eid, AttributeMap::default().eid);
+        assert_eq!(txstate.branding.redeem_url, None);
+
+        let ut = InstitutionProfile::utexas();
+        assert_eq!(ut.domains(), vec!["eid.utexas.edu", "utexas.edu", "austin.utexas.edu"]);
+        assert_eq!(ut.parse_eid("BHA366@austin.utexas.edu").unwrap().as_str(), "bha366");
+        assert!(txstate.parse_eid("bha366@utexas.edu").is_err());
+        assert_eq!(txstate.parse_eid("abc12@txstate.edu").unwrap().as_str(), "abc12");
+    }
+
+    #[test]
+    fn refuses_partial_or_misspelled_profiles() {
+        // leaving out where the directory or mail is would silently fall back to UT's
+        for field in ["directory_url", "base_dn", "email_domain", "email_aliases"] {
+            let mut partial: serde_json::Value = serde_json::from_str(&profile("txstate")).unwrap();
+            partial.as_object_mut().unwrap().remove(field);
+            assert!(
+                matches!(InstitutionProfile::parse_all(&format!("[{}]", partial)), Err(InvalidInstitutions::Json(_))),
+                "{} was not required",
+                field
+            );
+        }
+
+        let misspelled = profile("txstate").replace("email_aliases", "email_alias");
+        assert!(InstitutionProfile::parse_all(&format!("[{}]", misspelled)).is_err());
+        let misspelled = profile("txstate").replace(r#""id""#, r#""branding": { "id_lable": "NetID" }, "id""#);
+        assert!(InstitutionProfile::parse_all(&format!("[{}]", misspelled)).is_err());
+    }
+
+    #[test]
+    fn requires_distinct_ids() {
+        assert!(matches!(
+            InstitutionProfile::parse_all(&format!("[{}]", profile("txstate").replace(r#""id": "txstate", "#, ""))),
+            Err(InvalidInstitutions::Json(_))
+        ));
+        assert!(matches!(
+            InstitutionProfile::parse_all(&format!("[{}, {}, {}]", profile("txstate"), profile("utexas"), profile("txstate"))),
+            Err(InvalidInstitutions::DuplicateId(id)) if id == "txstate"
+        ));
+    }
+
+    #[test]
+    fn derives_encryption_keys_for_other_institutions() {
+        let key = [7; 32];
+        let profiles =
+            InstitutionProfile::parse_all(&format!("[{}, {}, {}]", profile("utexas"), profile("txstate"), profile("unt"))).unwrap();
+
+        assert_eq!(profiles[0].encryption_key(&key), key);
+        assert_eq!(InstitutionProfile::utexas().encryption_key(&key), key);
+
+        let txstate = profiles[1].encryption_key(&key);
+        assert_eq!(txstate.len(), 32);
+        assert_ne!(txstate, key);
+        assert_ne!(txstate, profiles[2].encryption_key(&key));
+        assert_eq!(txstate, profiles[1].encryption_key(&key));
+    }
+}
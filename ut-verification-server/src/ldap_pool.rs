@@ -0,0 +1,131 @@
+//! Long-lived LDAP connections to the directory, shared between lookups.
+//!
+//! Connections are reused rather than opened per batch, checked before reuse if they sat
+//! idle for a while, and reopened with backoff when the directory is unreachable.
+
+use std::ops::{Deref, DerefMut};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use ldap3::{Ldap, LdapConnAsync, LdapConnSettings, LdapError, Scope};
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_CONNECT_ATTEMPTS: u32 = 5;
+const INITIAL_BACKOFF: Duration = Duration::from_millis(200);
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+/// Idle connections older than this are checked before being handed out.
+const HEALTH_CHECK_AFTER: Duration = Duration::from_secs(30);
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+struct Idle {
+    ldap: Ldap,
+    since: Instant,
+}
+
+pub struct LdapPool {
+    url: String,
+    idle: Mutex<Vec<Idle>>,
+    /// Bounds the number of connections, and so of concurrent lookups
+    permits: Semaphore,
+}
+
+impl LdapPool {
+    /// Creates a pool that opens at most `max_connections` connections to `url`, as needed.
+    pub fn new(url: &str, max_connections: usize) -> Self {
+        LdapPool {
+            url: url.to_owned(),
+            idle: Mutex::new(Vec::new()),
+            permits: Semaphore::new(max_connections),
+        }
+    }
+
+    /// Waits for a free connection, opening a new one if none are idle.
+    pub async fn get(&self) -> Result<PooledLdap<'_>, LdapError> {
+        let permit = self.permits.acquire().await.expect("pool semaphore closed");
+
+        loop {
+            let idle = self.idle.lock().unwrap().pop();
+            let mut idle = match idle {
+                Some(idle) => idle,
+                None => break,
+            };
+            if idle.since.elapsed() < HEALTH_CHECK_AFTER || is_healthy(&mut idle.ldap).await {
+                return Ok(PooledLdap { pool: self, ldap: Some(idle.ldap), _permit: permit });
+            }
+        }
+
+        let ldap = self.connect().await?;
+        Ok(PooledLdap { pool: self, ldap: Some(ldap), _permit: permit })
+    }
+
+    async fn connect(&self) -> Result<Ldap, LdapError> {
+        let mut backoff = INITIAL_BACKOFF;
+        let mut attempt = 1;
+
+        loop {
+            match LdapConnAsync::with_settings(LdapConnSettings::new().set_conn_timeout(CONNECT_TIMEOUT), &self.url).await {
+                Ok((conn, ldap)) => {
+                    tokio::spawn(async move {
+                        if let Err(err) = conn.drive().await {
+                            eprintln!("directory connection closed: {}", err);
+                        }
+                    });
+                    return Ok(ldap);
+                }
+                Err(err) if attempt >= MAX_CONNECT_ATTEMPTS => return Err(err),
+                Err(err) => {
+                    eprintln!("failed to connect to directory (attempt {}): {}", attempt, err);
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+/// Reads the root DSE, which every directory allows.
+async fn is_healthy(ldap: &mut Ldap) -> bool {
+    let res = ldap
+        .with_timeout(HEALTH_CHECK_TIMEOUT)
+        .search("", Scope::Base, "(objectClass=*)", vec!["1.1"])
+        .await;
+    res.and_then(|res| res.success()).is_ok()
+}
+
+/// A connection borrowed from an [`LdapPool`], returned to it when dropped.
+pub struct PooledLdap<'a> {
+    pool: &'a LdapPool,
+    ldap: Option<Ldap>,
+    _permit: SemaphorePermit<'a>,
+}
+
+impl PooledLdap<'_> {
+    /// Closes the connection instead of returning it, e.g. after it failed.
+    pub fn discard(mut self) {
+        self.ldap = None;
+    }
+}
+
+impl Deref for PooledLdap<'_> {
+    type Target = Ldap;
+
+    fn deref(&self) -> &Ldap {
+        self.ldap.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for PooledLdap<'_> {
+    fn deref_mut(&mut self) -> &mut Ldap {
+        self.ldap.as_mut().unwrap()
+    }
+}
+
+impl Drop for PooledLdap<'_> {
+    fn drop(&mut self) {
+        if let Some(ldap) = self.ldap.take() {
+            self.pool.idle.lock().unwrap().push(Idle { ldap, since: Instant::now() });
+        }
+    }
+}
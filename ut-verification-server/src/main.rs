@@ -1,25 +1,72 @@
+use std::collections::HashMap;
+
 use handlebars::Handlebars;
 use lettre_email::EmailBuilder;
 use serde_json::json;
 use serde::Deserialize;
 use lazy_static::lazy_static;
 use aws_sdk_sqs::{self, model::DeleteMessageBatchRequestEntry};
-use ldap3::{LdapConnAsync, Ldap};
+use futures::future::join_all;
 
 use utv_token;
 
-use crate::directory::Person;
+use crate::codes::PendingCodes;
+use crate::institution::{InstitutionProfile, DEFAULT_INSTITUTION};
+use crate::directory::{DirectoryBackend, LdapDirectory, LookupError, MemoryDirectory};
+use crate::directory_cache::CachedDirectory;
+use crate::ldap_pool::LdapPool;
 use mail_sender::MailSender;
 
+mod attributes;
+mod codes;
 mod deterministic_aes;
 mod directory;
+mod directory_cache;
+mod eid;
+mod institution;
+mod ldap_pool;
 mod mail_sender;
+mod redeem;
 
 lazy_static! {
-    static ref SHARED_KEY: Vec<u8> = {
-        let shared_key = std::env::var("SHARED_KEY").expect("Missing SHARED_KEY");
-        base64::decode_config(shared_key, base64::URL_SAFE_NO_PAD).expect("Invalid SHARED_KEY")
+    /// Tokens are signed with the Ed25519 key in `SIGNING_KEY` (a base64 PKCS#8 document,
+    /// with id `SIGNING_KEY_ID`) when set, so consumers only need its public key.
+    ///
+    /// Otherwise, `SHARED_KEYS` lists every HMAC key tokens may be verified against as
+    /// `id:key,...`, and `SHARED_KEY_ID` picks the one new tokens are signed with. Deployments
+    /// that predate key ids only set `SHARED_KEY`, which becomes key 0.
+    static ref SIGNING_KEYS: Box<dyn utv_token::SigningKeys + Send + Sync> = {
+        match (std::env::var("SIGNING_KEY"), std::env::var("SHARED_KEYS")) {
+            (Ok(signing_key), _) => {
+                let pkcs8 = base64::decode_config(signing_key, base64::URL_SAFE_NO_PAD).expect("Invalid SIGNING_KEY");
+                let id = std::env::var("SIGNING_KEY_ID").expect("Missing SIGNING_KEY_ID")
+                    .parse().expect("Invalid SIGNING_KEY_ID");
+                Box::new(utv_token::SigningKey::from_pkcs8(id, &pkcs8).expect("Invalid SIGNING_KEY"))
+            }
+            (Err(_), Ok(shared_keys)) => {
+                let active = std::env::var("SHARED_KEY_ID").expect("Missing SHARED_KEY_ID")
+                    .parse().expect("Invalid SHARED_KEY_ID");
+                Box::new(utv_token::Keyring::parse(&shared_keys, active).expect("Invalid SHARED_KEYS"))
+            }
+            (Err(_), Err(_)) => {
+                let shared_key = std::env::var("SHARED_KEY").expect("Missing SHARED_KEYS or SHARED_KEY");
+                let shared_key = base64::decode_config(shared_key, base64::URL_SAFE_NO_PAD).expect("Invalid SHARED_KEY");
+                Box::new(utv_token::Keyring::new(0, &shared_key))
+            }
+        }
     };
+    /// When `SEALING_KEYS` (`id:key,...`, with `SEALING_KEY_ID` active) is set, tokens are
+    /// encrypted rather than signed, so the emailed link reveals nothing about the student.
+    /// The website must then be given the same `SEALING_KEYS` to open them.
+    static ref SEALING_KEYS: Option<utv_token::SealingKeyring> = {
+        std::env::var("SEALING_KEYS").ok().map(|sealing_keys| {
+            let active = std::env::var("SEALING_KEY_ID").expect("Missing SEALING_KEY_ID")
+                .parse().expect("Invalid SEALING_KEY_ID");
+            utv_token::SealingKeyring::parse(&sealing_keys, active).expect("Invalid SEALING_KEYS")
+        })
+    };
+    /// Codes emailed to requests that asked for one, until they are redeemed.
+    static ref CODES: PendingCodes = PendingCodes::new();
     static ref ENCRYPTION_KEY: Vec<u8> = {
         let encryption_key = std::env::var("ENCRYPTION_KEY").expect("Missing ENCRYPTION_KEY");
         base64::decode_config(encryption_key, base64::URL_SAFE_NO_PAD).expect("Invalid ENCRYPTION_KEY")
@@ -33,36 +80,120 @@ lazy_static! {
 }
 
 static TEMPLATE: &'static str = include_str!("./email.hbs");
+static CODE_TEMPLATE: &'static str = include_str!("./email_code.hbs");
 const REQUESTS_PER_SECOND: i32 = 10;
+/// Directory connections kept open, which also bounds how many lookups run at once.
+const DIRECTORY_CONNECTIONS: usize = 4;
+/// EIDs whose directory lookups are remembered per institution, see `directory_cache`.
+const DIRECTORY_CACHE_SIZE: usize = 10_000;
 
 #[derive(Deserialize)]
 struct VerificationRequest<'a> {
-    eid: &'a str
+    eid: &'a str,
+    service: &'a str,
+    service_id: &'a str,
+    /// Guild ids or service names the token is restricted to, if any
+    #[serde(default)]
+    aud: Vec<String>,
+    /// Email a short code to be redeemed through `REDEEM_ADDR`, rather than a token link,
+    /// if the institution has a page to enter it on
+    #[serde(default)]
+    code: bool,
+    /// Id of the institution profile the EID belongs to, UT Austin by default
+    #[serde(default)]
+    institution: Option<&'a str>,
+}
+
+/// An institution's profile, along with where its people are looked up.
+struct Institution {
+    profile: InstitutionProfile,
+    directory: Box<dyn DirectoryBackend>,
+}
+
+impl Institution {
+    /// Connects to the profile's directory, or the fixture in `DIRECTORY_FIXTURE` when set.
+    fn new(profile: InstitutionProfile) -> Self {
+        let encryption_key = profile.encryption_key(&ENCRYPTION_KEY);
+        let directory: Box<dyn DirectoryBackend> = match std::env::var("DIRECTORY_FIXTURE") {
+            Ok(path) => {
+                let json = std::fs::read_to_string(path).expect("Invalid DIRECTORY_FIXTURE");
+                Box::new(MemoryDirectory::from_json(&json, &profile.id_syntax, &encryption_key).expect("Invalid DIRECTORY_FIXTURE"))
+            }
+            Err(_) => {
+                let pool = LdapPool::new(&profile.directory_url, DIRECTORY_CONNECTIONS);
+                let ldap = LdapDirectory::new(pool, &profile.base_dn, profile.attributes.clone(), &encryption_key);
+                Box::new(CachedDirectory::new(ldap, DIRECTORY_CACHE_SIZE))
+            }
+        };
+        Institution { profile, directory }
+    }
+}
+
+/// Encodes claims as the emailed token, or the token a redeemed code is exchanged for.
+fn mint_token(claims: &utv_token::VerifiedClaims) -> String {
+    match &*SEALING_KEYS {
+        Some(keys) => utv_token::seal_token(claims, keys),
+        None => utv_token::encode_token(claims, &**SIGNING_KEYS),
+    }
 }
 
-async fn request_verification<'a>(mail_sender: &MailSender, ldap: &mut Ldap, req: VerificationRequest<'a>) {
-    let eid = req.eid;
-    let res = Person::lookup(ldap, eid, &ENCRYPTION_KEY).await;
+/// Looks up and emails the requester, failing only when the directory could not be reached,
+/// in which case the request should be retried.
+async fn request_verification<'a>(
+    mail_sender: &MailSender,
+    institutions: &HashMap<String, Institution>,
+    req: VerificationRequest<'a>,
+) -> Result<(), LookupError> {
+    let institution = match institutions.get(req.institution.unwrap_or(DEFAULT_INSTITUTION)) {
+        Some(institution) => institution,
+        None => {
+            eprintln!("ignoring request for unknown institution {:?}", req.institution);
+            return Ok(());
+        }
+    };
+    let profile = &institution.profile;
+
+    let eid = match profile.parse_eid(req.eid) {
+        Ok(eid) => eid,
+        Err(err) => {
+            eprintln!("ignoring request for {:?}: {}", req.eid, err);
+            return Ok(());
+        }
+    };
+    let res = institution.directory.lookup(&eid).await;
     match res {
         Ok(person) => {
-            let email = format!("{}@eid.utexas.edu", eid);
-            let token = utv_token::encode_token(&person.claims, &SHARED_KEY);
+            let email = format!("{}@{}", eid, profile.email_domain);
+            let mut claims = person.claims(&profile.id, req.service, req.service_id);
+            claims.aud = req.aud;
 
+            let branding = &profile.branding;
+            let mut vars = json!({
+                "name": person.name,
+                "id_label": branding.id_label,
+                "support_email": branding.support_email,
+                "verify_url": branding.verify_url,
+            });
             let reg = Handlebars::new();
-            let body = reg
-                .render_template(
-                    TEMPLATE,
-                    &json!({
-                        "name": person.name,
-                        "token": token
-                    }),
-                )
-                .unwrap();
+            let redeem_url = branding.redeem_url.as_deref().filter(|_| req.code);
+            if req.code && redeem_url.is_none() {
+                eprintln!("sending a link rather than a code, as {} has no redeem_url", profile.id);
+            }
+            let body = if let Some(redeem_url) = redeem_url {
+                vars["redeem_url"] = redeem_url.into();
+                vars["code"] = CODES.issue(claims).into();
+                vars["expires_in"] = (utv_token::TOKEN_LIFETIME.as_secs() / 60).into();
+                reg.render_template(CODE_TEMPLATE, &vars)
+            } else {
+                vars["token"] = mint_token(&claims).into();
+                reg.render_template(TEMPLATE, &vars)
+            }
+            .unwrap();
 
             let email = EmailBuilder::new()
                 .to((email, person.name))
                 .from(FROM_ADDRESS.as_str())
-                .subject("[Discord VerifiedBot] Verify your UT EID")
+                .subject(format!("[{}] Verify your {}", branding.product, branding.id_label))
                 .text(body)
                 .build()
                 .unwrap()
@@ -72,11 +203,32 @@ async fn request_verification<'a>(mail_sender: &MailSender, ldap: &mut Ldap, req
                 panic!();
             }
         },
+        Err(err @ LookupError::LdapError(_)) => {
+            eprintln!("could not reach directory for eid {}: {:#?}", eid, err);
+            return Err(err);
+        }
         Err(err) => {
             eprintln!("had a lookup error for eid {}: {:#?}", eid, err);
         }
     }
 
+    Ok(())
+}
+
+/// Handles a queued message, dropping it if it isn't a valid request, e.g. one queued by an
+/// older version of the website.
+async fn handle_message(
+    mail_sender: &MailSender,
+    institutions: &HashMap<String, Institution>,
+    body: &str,
+) -> Result<(), LookupError> {
+    match serde_json::from_str(body) {
+        Ok(req) => request_verification(mail_sender, institutions, req).await,
+        Err(err) => {
+            eprintln!("dropping invalid message {:?}: {}", body, err);
+            Ok(())
+        }
+    }
 }
 
 #[tokio::main]
@@ -86,8 +238,20 @@ async fn main() {
 
     let mail_sender = mail_sender::spawn();
 
+    if let Ok(addr) = std::env::var("REDEEM_ADDR") {
+        let addr = addr.parse().expect("Invalid REDEEM_ADDR");
+        let auth = std::env::var("REDEEM_AUTH").expect("Missing REDEEM_AUTH");
+        tokio::spawn(redeem::serve(addr, auth));
+    }
+
+    let institutions: HashMap<String, Institution> = InstitutionProfile::load_all()
+        .into_iter()
+        .map(|profile| (profile.id.clone(), Institution::new(profile)))
+        .collect();
+
     loop {
         tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        CODES.purge_expired(utv_token::Clock::now(&utv_token::SystemClock));
         let out = client
             .receive_message()
             .queue_url(SQS_VERIFICATION_REQUEST_URL.as_str())
@@ -101,20 +265,23 @@ async fn main() {
             None => continue
         };
 
-        let mut entries = Vec::new();
+        let bodies: Vec<String> = messages.iter().map(|msg| msg.body.clone().unwrap_or_default()).collect();
+        let results = join_all(bodies.iter().map(|body| handle_message(&mail_sender, &institutions, body))).await;
 
-        let (conn, mut ldap) = LdapConnAsync::new("ldap://directory.utexas.edu:389").await.expect("failed to connect to directory");
-        tokio::spawn(conn.drive());
-
-        for msg in messages {
-            let body = msg.body.expect("invalid message received");
-            let req: VerificationRequest = serde_json::from_str(&body).expect("invalid message received");
-            request_verification(&mail_sender, &mut ldap, req).await;
-            entries.push(
-                DeleteMessageBatchRequestEntry::builder()
-                .set_id(Some(entries.len().to_string()))
-                .set_receipt_handle( msg.receipt_handle)
-                .build());
+        // requests that failed because the directory was down stay queued, and are retried,
+        // while malformed ones are deleted along with the rest so they aren't redelivered
+        let mut entries = Vec::new();
+        for (msg, res) in messages.into_iter().zip(results) {
+            if res.is_ok() {
+                entries.push(
+                    DeleteMessageBatchRequestEntry::builder()
+                    .set_id(Some(entries.len().to_string()))
+                    .set_receipt_handle(msg.receipt_handle)
+                    .build());
+            }
+        }
+        if entries.is_empty() {
+            continue;
         }
 
         client
@@ -123,7 +290,5 @@ async fn main() {
             .set_entries(Some(entries))
             .send()
             .await.unwrap();
-
-        ldap.unbind().await.unwrap();
     }
 }
@@ -0,0 +1,67 @@
+//! HTTP endpoint for redeeming emailed codes, for the website to call.
+//!
+//! `POST /redeem` with `{"service", "service_id", "code"}` as JSON, and `REDEEM_AUTH` as the
+//! `Authorization` header. A correct code is answered with a regular token for the same
+//! claims, so that the rest of the verification flow doesn't change.
+
+use std::convert::Infallible;
+use std::net::SocketAddr;
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde::Deserialize;
+use utv_token::SystemClock;
+
+use crate::codes::RedeemError;
+use crate::{mint_token, CODES};
+
+#[derive(Deserialize)]
+struct RedeemRequest {
+    service: String,
+    service_id: String,
+    code: String,
+}
+
+fn respond(status: StatusCode, body: impl Into<Body>) -> Response<Body> {
+    Response::builder().status(status).body(body.into()).unwrap()
+}
+
+async fn handle(req: Request<Body>, auth: &str) -> Response<Body> {
+    if req.method() != Method::POST || req.uri().path() != "/redeem" {
+        return respond(StatusCode::NOT_FOUND, "Not Found");
+    }
+    let authorized = matches!(
+        req.headers().get(hyper::header::AUTHORIZATION),
+        Some(header) if ring::constant_time::verify_slices_are_equal(header.as_bytes(), auth.as_bytes()).is_ok()
+    );
+    if !authorized {
+        return respond(StatusCode::UNAUTHORIZED, "Unauthorized");
+    }
+
+    let body = match hyper::body::to_bytes(req.into_body()).await {
+        Ok(body) => body,
+        Err(_) => return respond(StatusCode::BAD_REQUEST, "Bad Request"),
+    };
+    let req: RedeemRequest = match serde_json::from_slice(&body) {
+        Ok(req) => req,
+        Err(_) => return respond(StatusCode::BAD_REQUEST, "Bad Request"),
+    };
+
+    match CODES.redeem(&req.service, &req.service_id, &req.code, &SystemClock) {
+        Ok(claims) => respond(StatusCode::OK, mint_token(&claims)),
+        Err(RedeemError::WrongCode) => respond(StatusCode::FORBIDDEN, "Wrong code"),
+        Err(RedeemError::TooManyAttempts) => respond(StatusCode::FORBIDDEN, "Too many attempts. Request a new code."),
+        Err(RedeemError::NoPendingCode | RedeemError::Expired) => respond(StatusCode::GONE, "No pending code. Request a new one."),
+    }
+}
+
+pub async fn serve(addr: SocketAddr, auth: String) {
+    let auth: &'static str = Box::leak(auth.into_boxed_str());
+    let make_service = make_service_fn(move |_| async move {
+        Ok::<_, Infallible>(service_fn(move |req| async move { Ok::<_, Infallible>(handle(req, auth).await) }))
+    });
+
+    if let Err(err) = Server::bind(&addr).serve(make_service).await {
+        eprintln!("redeem server failed: {}", err);
+    }
+}
@@ -0,0 +1,59 @@
+use std::fmt;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// A person's relationship to the university, as listed in the directory.
+///
+/// Serialized as the directory's lowercase string, e.g. `"student"`. Values are matched
+/// ignoring case and surrounding whitespace, and anything unrecognised is kept as `Other`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Affiliation {
+    Student,
+    Faculty,
+    Staff,
+    Affiliate,
+    Retiree,
+    Other(String),
+}
+
+impl Affiliation {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Student => "student",
+            Self::Faculty => "faculty",
+            Self::Staff => "staff",
+            Self::Affiliate => "affiliate",
+            Self::Retiree => "retiree",
+            Self::Other(other) => other,
+        }
+    }
+}
+
+impl From<&str> for Affiliation {
+    fn from(affiliation: &str) -> Self {
+        let known = [Self::Student, Self::Faculty, Self::Staff, Self::Affiliate, Self::Retiree];
+        let trimmed = affiliation.trim();
+        known
+            .into_iter()
+            .find(|known| known.as_str().eq_ignore_ascii_case(trimmed))
+            .unwrap_or_else(|| Self::Other(trimmed.to_owned()))
+    }
+}
+
+impl fmt::Display for Affiliation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Serialize for Affiliation {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Affiliation {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(|affiliation| Self::from(affiliation.as_str()))
+    }
+}
@@ -0,0 +1,239 @@
+//! Command-line tool for minting, inspecting and verifying tokens.
+//!
+//! Key files use the same `id:key` format as `SHARED_KEYS`, with entries separated by
+//! commas or newlines. Tokens are minted with the first key in the file.
+
+use std::io::Read;
+use std::process::exit;
+
+use utv_token::{
+    Clock, Keyring, PublicKeyring, RevocationList, SealingKeyring, SigningKey, SystemClock, Validation, VerifiedClaims,
+};
+
+const USAGE: &str = "\
+usage:
+    utv-token mint [--ed25519 | --sealed] <key-file> [<claims.json>]
+        sign claims read from a file, or stdin, with the first key in <key-file>.
+        iat, exp and jti are filled in when missing.
+    utv-token inspect <token>
+        print a token's header and claims, WITHOUT checking its signature
+    utv-token verify [--ed25519 | --sealed | --legacy <id:deadline>] <key-file> <token> [<revocation-list>]
+        check a token's signature, then its expiry and revocation, and print its claims
+    utv-token revoke <revocation-list> <token>
+        add a token to a revocation list, creating the list if it does not exist.
+        the token is not verified, so sealed tokens must be revoked by eid on the server
+
+    --ed25519   key file holds Ed25519 keys: PKCS#8 documents to mint, public keys to verify
+    --sealed    key file holds AES-256-GCM-SIV keys for encrypted tokens
+    --legacy    accept unversioned tokens signed with key <id> until the unix time <deadline>";
+
+enum Mode {
+    Hmac,
+    Ed25519,
+    Sealed,
+}
+
+fn fail(msg: impl std::fmt::Display) -> ! {
+    eprintln!("{}", msg);
+    exit(1)
+}
+
+fn read_key_file(path: &str) -> (u8, String) {
+    let keys = std::fs::read_to_string(path)
+        .unwrap_or_else(|err| fail(format!("could not read {}: {}", path, err)))
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(",");
+    let first_id = keys
+        .split(',')
+        .find(|entry| !entry.is_empty())
+        .and_then(|entry| entry.split(':').next())
+        .and_then(|id| id.parse().ok())
+        .unwrap_or_else(|| fail(format!("{} does not contain any id:key entries", path)));
+    (first_id, keys)
+}
+
+fn read_claims(path: Option<&str>) -> VerifiedClaims {
+    let mut json = String::new();
+    match path {
+        Some(path) => json = std::fs::read_to_string(path).unwrap_or_else(|err| fail(format!("could not read {}: {}", path, err))),
+        None => {
+            std::io::stdin().read_to_string(&mut json).unwrap_or_else(|err| fail(err));
+        }
+    }
+
+    let mut claims: serde_json::Value = serde_json::from_str(&json).unwrap_or_else(|err| fail(format!("invalid claims: {}", err)));
+    let fields = claims.as_object_mut().unwrap_or_else(|| fail("claims must be a json object"));
+    let iat = SystemClock.now();
+    fields.entry("iat").or_insert_with(|| iat.into());
+    fields.entry("exp").or_insert_with(|| (iat + utv_token::TOKEN_LIFETIME.as_secs()).into());
+    fields.entry("jti").or_insert_with(|| utv_token::new_token_id().into());
+
+    serde_json::from_value(claims).unwrap_or_else(|err| fail(format!("invalid claims: {}", err)))
+}
+
+fn print_claims(claims: &VerifiedClaims) {
+    println!("{}", serde_json::to_string_pretty(claims).unwrap());
+}
+
+fn mint(mode: Mode, args: &[String]) {
+    let (key_file, claims_file) = match args {
+        [key_file] => (key_file, None),
+        [key_file, claims_file] => (key_file, Some(claims_file.as_str())),
+        _ => fail(USAGE),
+    };
+    let (id, keys) = read_key_file(key_file);
+    let claims = read_claims(claims_file);
+
+    let token = match mode {
+        Mode::Hmac => {
+            let keys = Keyring::parse(&keys, id).unwrap_or_else(|_| fail("invalid key file"));
+            utv_token::encode_token(&claims, &keys)
+        }
+        Mode::Ed25519 => {
+            let pkcs8 = keys.split(',').next().and_then(|entry| entry.split_once(':')).map(|(_, key)| key).unwrap_or("");
+            let pkcs8 = base64::decode_config(pkcs8, base64::URL_SAFE_NO_PAD).unwrap_or_else(|_| fail("invalid key file"));
+            let key = SigningKey::from_pkcs8(id, &pkcs8).unwrap_or_else(|_| fail("invalid key file"));
+            utv_token::encode_token(&claims, &key)
+        }
+        Mode::Sealed => {
+            let keys = SealingKeyring::parse(&keys, id).unwrap_or_else(|_| fail("invalid key file"));
+            utv_token::seal_token(&claims, &keys)
+        }
+    };
+
+    println!("{}", token);
+}
+
+fn inspect(args: &[String]) {
+    let token = match args {
+        [token] => token.trim(),
+        _ => fail(USAGE),
+    };
+
+    let info = utv_token::inspect_token(token).unwrap_or_else(|err| fail(err));
+    println!("version: {}", info.version);
+    match info.key_id {
+        Some(id) => println!("key id: {}", id),
+        None => println!("key id: none"),
+    }
+    println!("signature: not checked");
+    match info.claims {
+        Some(claims) => print_claims(&claims),
+        None => println!("claims: encrypted"),
+    }
+}
+
+fn verify(mode: Mode, legacy: Option<(u8, u64)>, args: &[String]) {
+    let (key_file, token, revocations) = match args {
+        [key_file, token] => (key_file, token.trim(), None),
+        [key_file, token, list_file] => (key_file, token.trim(), Some(read_revocation_list(list_file))),
+        _ => fail(USAGE),
+    };
+    let (id, keys) = read_key_file(key_file);
+    let mut validation = Validation::new(&SystemClock);
+    if let Some(revocations) = &revocations {
+        validation = validation.revocations(revocations);
+    }
+
+    // the signature is checked on its own first, so that an expired or revoked token
+    // isn't reported as forged
+    let (signature, checks) = match mode {
+        Mode::Hmac => {
+            let mut keys = Keyring::parse(&keys, id).unwrap_or_else(|_| fail("invalid key file"));
+            if let Some((id, deadline)) = legacy {
+                keys.accept_legacy(id, deadline);
+            }
+            (utv_token::decode_token(token, &keys), utv_token::decode_token_with(token, &keys, &validation))
+        }
+        _ if legacy.is_some() => fail("--legacy only applies to HMAC keys, which signed every unversioned token"),
+        Mode::Ed25519 => {
+            let keys = PublicKeyring::parse(&keys).unwrap_or_else(|_| fail("invalid key file"));
+            (utv_token::decode_token(token, &keys), utv_token::decode_token_with(token, &keys, &validation))
+        }
+        Mode::Sealed => {
+            let keys = SealingKeyring::parse(&keys, id).unwrap_or_else(|_| fail("invalid key file"));
+            (utv_token::open_sealed_token(token, &keys), utv_token::open_sealed_token_with(token, &keys, &validation))
+        }
+    };
+
+    let claims = signature.unwrap_or_else(|err| fail(format!("signature: invalid ({})", err)));
+    println!("signature: valid");
+    let checks = checks.err();
+    match &checks {
+        None => println!("checks: passed"),
+        Some(err) => println!("checks: failed ({})", err),
+    }
+    print_claims(&claims);
+    if checks.is_some() {
+        exit(1);
+    }
+}
+
+/// Parses `id:deadline`, the key that signed unversioned tokens and the unix time until
+/// which they are accepted.
+fn parse_legacy(legacy: &str) -> (u8, u64) {
+    legacy
+        .split_once(':')
+        .and_then(|(id, deadline)| Some((id.trim().parse().ok()?, deadline.trim().parse().ok()?)))
+        .unwrap_or_else(|| fail("--legacy must be given as id:deadline"))
+}
+
+fn read_revocation_list(path: &str) -> RevocationList {
+    match std::fs::read(path) {
+        Ok(data) => RevocationList::from_bytes(&data).unwrap_or_else(|_| fail(format!("{} is not a revocation list", path))),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => RevocationList::new(),
+        Err(err) => fail(format!("could not read {}: {}", path, err)),
+    }
+}
+
+fn revoke(args: &[String]) {
+    let (list_file, token) = match args {
+        [list_file, token] => (list_file, token.trim()),
+        _ => fail(USAGE),
+    };
+
+    let claims = utv_token::inspect_token(token)
+        .unwrap_or_else(|err| fail(err))
+        .claims
+        .unwrap_or_else(|| fail("sealed tokens cannot be read without their key"));
+    if claims.jti.is_empty() {
+        fail("unversioned tokens have no id to revoke");
+    }
+
+    let mut revocations = read_revocation_list(list_file);
+    revocations.purge_expired(SystemClock.now());
+    revocations.revoke_token(&claims.jti, claims.exp);
+    std::fs::write(list_file, revocations.to_bytes()).unwrap_or_else(|err| fail(format!("could not write {}: {}", list_file, err)));
+    println!("revoked {}", claims.jti);
+}
+
+fn main() {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+
+    let legacy = args.iter().position(|a| a == "--legacy").map(|i| {
+        args.remove(i);
+        if i == args.len() {
+            fail(USAGE);
+        }
+        parse_legacy(&args.remove(i))
+    });
+
+    let mode = if let Some(i) = args.iter().position(|a| a == "--ed25519") {
+        args.remove(i);
+        Mode::Ed25519
+    } else if let Some(i) = args.iter().position(|a| a == "--sealed") {
+        args.remove(i);
+        Mode::Sealed
+    } else {
+        Mode::Hmac
+    };
+
+    match args.first().map(String::as_str) {
+        Some("mint") => mint(mode, &args[1..]),
+        Some("inspect") => inspect(&args[1..]),
+        Some("verify") => verify(mode, legacy, &args[1..]),
+        Some("revoke") => revoke(&args[1..]),
+        _ => fail(USAGE),
+    }
+}
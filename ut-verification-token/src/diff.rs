@@ -0,0 +1,90 @@
+//! Changes between two sets of claims for the same person, e.g. on re-verification.
+
+use crate::{Affiliation, VerifiedClaims};
+
+/// Values gained and lost by a single field.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldDiff<T> {
+    pub added: Vec<T>,
+    pub removed: Vec<T>,
+}
+
+impl<T: PartialEq + Clone> FieldDiff<T> {
+    fn between(old: &[T], new: &[T]) -> Self {
+        FieldDiff {
+            added: new.iter().filter(|v| !old.contains(v)).cloned().collect(),
+            removed: old.iter().filter(|v| !new.contains(v)).cloned().collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// What changed in the directory fields of a person's claims, see [`VerifiedClaims::diff`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClaimsDiff {
+    pub major: FieldDiff<String>,
+    pub school: FieldDiff<String>,
+    pub affiliation: FieldDiff<Affiliation>,
+    pub classification: FieldDiff<String>,
+    pub department: FieldDiff<String>,
+    pub title: FieldDiff<String>,
+    pub graduation: FieldDiff<String>,
+}
+
+impl ClaimsDiff {
+    pub fn is_empty(&self) -> bool {
+        self.major.is_empty()
+            && self.school.is_empty()
+            && self.affiliation.is_empty()
+            && self.classification.is_empty()
+            && self.department.is_empty()
+            && self.title.is_empty()
+            && self.graduation.is_empty()
+    }
+}
+
+impl VerifiedClaims {
+    /// Values added and removed per field in going from these claims to `other`.
+    ///
+    /// Only the directory fields are compared; timestamps, account and token id differ
+    /// between any two tokens.
+    pub fn diff(&self, other: &VerifiedClaims) -> ClaimsDiff {
+        ClaimsDiff {
+            major: FieldDiff::between(&self.major, &other.major),
+            school: FieldDiff::between(&self.school, &other.school),
+            affiliation: FieldDiff::between(&self.affiliation, &other.affiliation),
+            classification: FieldDiff::between(&self.classification, &other.classification),
+            department: FieldDiff::between(&self.department, &other.department),
+            title: FieldDiff::between(&self.title, &other.title),
+            graduation: FieldDiff::between(&self.graduation, &other.graduation),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::claims;
+
+    #[test]
+    fn diffs_claims_by_field() {
+        let old = claims(1000);
+        let mut new = claims(2000);
+        new.major.push("Mathematics".to_owned());
+        new.affiliation = vec![Affiliation::Staff];
+
+        let diff = old.diff(&new);
+        assert_eq!(diff.major, FieldDiff { added: vec!["Mathematics".to_owned()], removed: vec![] });
+        assert!(diff.school.is_empty());
+        assert_eq!(diff.affiliation.added, vec![Affiliation::Staff]);
+        assert_eq!(diff.affiliation.removed, vec![Affiliation::Student]);
+
+        assert!(old.diff(&claims(2000)).is_empty());
+
+        new.classification = vec!["graduate".to_owned()];
+        assert_eq!(old.diff(&new).classification.removed, vec!["senior"]);
+    }
+}
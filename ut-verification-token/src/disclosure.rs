@@ -0,0 +1,184 @@
+//! Selective disclosure of every field describing the student, see [`DISCLOSABLE_FIELDS`].
+//!
+//! Instead of the values themselves, the signed claims carry a salted SHA-256 commitment
+//! to each field in `sd`. The values travel alongside the token as disclosures, in the form
+//! `token~disclosure~disclosure...`. The holder can drop any disclosure before handing the
+//! token on, and the verifier only learns the fields whose disclosures are still attached,
+//! each checked against its commitment.
+
+use ring::digest;
+use ring::rand::{SecureRandom, SystemRandom};
+
+use crate::validation::Validation;
+use crate::{encode_token, Affiliation, open_token, SigningKeys, TokenError, VerifiedClaims, VerifyingKeys};
+
+/// Fields that can be withheld from a verifier.
+pub const DISCLOSABLE_FIELDS: [&str; 8] =
+    ["major", "school", "affiliation", "classification", "department", "title", "graduation", "institution"];
+
+const SEPARATOR: char = '~';
+
+/// Claims of a selectively disclosed token, where withheld fields are left empty.
+///
+/// In particular a withheld `institution` is empty, so check `disclosed` rather than
+/// treating it as UT's, as is done for tokens from before institutions were recorded.
+#[derive(Debug)]
+pub struct DisclosedClaims {
+    pub claims: VerifiedClaims,
+    /// Names of the fields that were disclosed
+    pub disclosed: Vec<String>,
+}
+
+/// Removes a disclosable field from the claims, returning its values.
+fn take_field(claims: &mut VerifiedClaims, field: &str) -> Vec<String> {
+    match field {
+        "major" => std::mem::take(&mut claims.major),
+        "school" => std::mem::take(&mut claims.school),
+        "affiliation" => std::mem::take(&mut claims.affiliation).iter().map(|a| a.to_string()).collect(),
+        "classification" => std::mem::take(&mut claims.classification),
+        "department" => std::mem::take(&mut claims.department),
+        "title" => std::mem::take(&mut claims.title),
+        "graduation" => std::mem::take(&mut claims.graduation),
+        "institution" => Some(std::mem::take(&mut claims.institution)).filter(|i| !i.is_empty()).into_iter().collect(),
+        _ => unreachable!("{} is not disclosable", field),
+    }
+}
+
+fn set_field(claims: &mut VerifiedClaims, field: &str, values: Vec<String>) -> Result<(), TokenError> {
+    match field {
+        "major" => claims.major = values,
+        "school" => claims.school = values,
+        "affiliation" => claims.affiliation = values.iter().map(|a| Affiliation::from(a.as_str())).collect(),
+        "classification" => claims.classification = values,
+        "department" => claims.department = values,
+        "title" => claims.title = values,
+        "graduation" => claims.graduation = values,
+        "institution" if values.len() <= 1 => claims.institution = values.into_iter().next().unwrap_or_default(),
+        _ => return Err(TokenError::BadDisclosure),
+    }
+    Ok(())
+}
+
+fn commitment(disclosure: &str) -> Vec<u8> {
+    digest::digest(&digest::SHA256, disclosure.as_bytes()).as_ref().to_vec()
+}
+
+/// Signs a token committing to each disclosable field, followed by a disclosure of every field.
+pub fn encode_selective_token<K: SigningKeys + ?Sized>(claims: &VerifiedClaims, keys: &K) -> String {
+    let rng = SystemRandom::new();
+    let mut blinded = claims.clone();
+    let mut disclosures = Vec::new();
+
+    for field in DISCLOSABLE_FIELDS {
+        let values = take_field(&mut blinded, field);
+
+        let mut salt = [0; 16];
+        rng.fill(&mut salt).unwrap();
+        let disclosure = rmp_serde::to_vec(&(&salt[..], field, values)).unwrap();
+        let disclosure = base64::encode_config(disclosure, base64::URL_SAFE_NO_PAD);
+
+        blinded.sd.push(commitment(&disclosure));
+        disclosures.push(disclosure);
+    }
+
+    let mut token = encode_token(&blinded, keys);
+    for disclosure in disclosures {
+        token.push(SEPARATOR);
+        token.push_str(&disclosure);
+    }
+    token
+}
+
+/// Drops the disclosures of every field not in `fields`, so they are hidden from the verifier.
+///
+/// This needs no keys, so it can be done by the holder of the token.
+pub fn disclose(token: &str, fields: &[&str]) -> Result<String, TokenError> {
+    let mut parts = token.split(SEPARATOR);
+    let mut disclosed = parts.next().unwrap_or_default().to_owned();
+
+    for disclosure in parts {
+        let (_, field, _) = open_disclosure(disclosure)?;
+        if fields.contains(&field.as_str()) {
+            disclosed.push(SEPARATOR);
+            disclosed.push_str(disclosure);
+        }
+    }
+
+    Ok(disclosed)
+}
+
+fn open_disclosure(disclosure: &str) -> Result<(Vec<u8>, String, Vec<String>), TokenError> {
+    let data = base64::decode_config(disclosure, base64::URL_SAFE_NO_PAD).map_err(TokenError::MalformedEncoding)?;
+    rmp_serde::from_read(&data[..]).map_err(|_| TokenError::BadDisclosure)
+}
+
+/// Decodes a selectively disclosed token, filling in the fields whose disclosures match
+/// the signed commitments.
+pub fn decode_selective_token<K: VerifyingKeys + ?Sized>(
+    token: &str,
+    keys: &K,
+    validation: &Validation,
+) -> Result<DisclosedClaims, TokenError> {
+    let mut parts = token.split(SEPARATOR);
+    let (version, mut claims) = open_token(parts.next().unwrap_or_default(), keys)?;
+    let mut disclosed = Vec::new();
+
+    for disclosure in parts {
+        let (_, field, values) = open_disclosure(disclosure)?;
+
+        let committed = claims.sd.contains(&commitment(disclosure));
+        if !committed || disclosed.contains(&field) {
+            return Err(TokenError::BadDisclosure);
+        }
+
+        set_field(&mut claims, &field, values)?;
+        disclosed.push(field);
+    }
+
+    // only once the disclosures check out, so that a bad one doesn't use up the token
+    validation.check(version, &claims, keys.legacy().map(|(_, deadline)| deadline))?;
+
+    Ok(DisclosedClaims { claims, disclosed })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::{claims, FixedClock};
+    use crate::Keyring;
+
+    #[test]
+    fn discloses_only_chosen_fields() {
+        let keys = Keyring::new(0, b"secret");
+        let validation = Validation::new(&FixedClock(1000));
+        let token = encode_selective_token(&claims(1000), &keys);
+
+        // nothing describing the student is left in the signed claims
+        let signed = base64::decode_config(token.split('~').next().unwrap(), base64::URL_SAFE_NO_PAD).unwrap();
+        for value in ["Computer Science", "student", "senior", "Spring 2025", "utexas"] {
+            assert!(!signed.windows(value.len()).any(|w| w == value.as_bytes()), "{} is not hidden", value);
+        }
+
+        let all = decode_selective_token(&token, &keys, &validation).unwrap();
+        assert_eq!(all.disclosed, DISCLOSABLE_FIELDS);
+        assert_eq!(all.claims.major, vec!["Computer Science"]);
+        assert_eq!(all.claims.classification, vec!["senior"]);
+        assert_eq!(all.claims.institution, "utexas");
+
+        let student_only = disclose(&token, &["affiliation"]).unwrap();
+        let decoded = decode_selective_token(&student_only, &keys, &validation).unwrap();
+        assert_eq!(decoded.disclosed, vec!["affiliation"]);
+        assert_eq!(decoded.claims.affiliation, vec![Affiliation::Student]);
+        assert!(decoded.claims.major.is_empty() && decoded.claims.school.is_empty());
+        assert!(decoded.claims.classification.is_empty() && decoded.claims.graduation.is_empty());
+        assert!(decoded.claims.institution.is_empty());
+
+        // a disclosure that was not committed to is refused
+        let mut other = claims(1000);
+        other.affiliation = vec![Affiliation::Faculty];
+        let forged = encode_selective_token(&other, &keys);
+        let forged_disclosure = forged.rsplit('~').next().unwrap();
+        let tampered = format!("{}~{}", student_only.split('~').next().unwrap(), forged_disclosure);
+        assert!(matches!(decode_selective_token(&tampered, &keys, &validation), Err(TokenError::BadDisclosure)));
+    }
+}
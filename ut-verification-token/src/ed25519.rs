@@ -0,0 +1,96 @@
+//! Asymmetric signing, so that token consumers only need a public key.
+//!
+//! ut-verification-server holds the private [`SigningKey`], and anything that redeems
+//! tokens verifies them with a [`PublicKeyring`], which cannot be used to mint new ones.
+
+use std::collections::BTreeMap;
+
+use ring::signature::{self, Ed25519KeyPair, KeyPair, UnparsedPublicKey, ED25519_PUBLIC_KEY_LEN};
+
+use crate::keyring::parse_keys;
+use crate::{InvalidKeyring, KeyId, SigningKeys, TokenError, VerifyingKeys, ED25519_TOKEN_VERSION};
+
+pub struct SigningKey {
+    id: KeyId,
+    pair: Ed25519KeyPair,
+}
+
+impl SigningKey {
+    /// Loads a private key from a PKCS#8 v2 document, as produced by `Ed25519KeyPair::generate_pkcs8`.
+    pub fn from_pkcs8(id: KeyId, pkcs8: &[u8]) -> Result<Self, InvalidKeyring> {
+        let pair = Ed25519KeyPair::from_pkcs8(pkcs8).map_err(|_| InvalidKeyring)?;
+        Ok(SigningKey { id, pair })
+    }
+
+    pub fn public_key(&self) -> &[u8] {
+        self.pair.public_key().as_ref()
+    }
+}
+
+impl SigningKeys for SigningKey {
+    fn version(&self) -> u8 {
+        ED25519_TOKEN_VERSION
+    }
+
+    fn key_id(&self) -> KeyId {
+        self.id
+    }
+
+    fn sign(&self, data: &[u8]) -> Vec<u8> {
+        self.pair.sign(data).as_ref().to_vec()
+    }
+}
+
+/// Public keys that Ed25519-signed tokens may be verified against.
+pub struct PublicKeyring {
+    keys: BTreeMap<KeyId, UnparsedPublicKey<Vec<u8>>>,
+}
+
+impl PublicKeyring {
+    /// Creates a keyring containing a single 32 byte public key.
+    pub fn new(id: KeyId, public_key: &[u8]) -> Result<Self, InvalidKeyring> {
+        let mut ring = PublicKeyring { keys: BTreeMap::new() };
+        ring.add_key(id, public_key)?;
+        Ok(ring)
+    }
+
+    /// Parses a comma-separated list of `id:public key` pairs, with keys encoded as url-safe base64.
+    pub fn parse(keys: &str) -> Result<Self, InvalidKeyring> {
+        let mut ring = PublicKeyring { keys: BTreeMap::new() };
+
+        for (id, key) in parse_keys(keys)? {
+            ring.add_key(id, &key)?;
+        }
+
+        if ring.keys.is_empty() {
+            return Err(InvalidKeyring);
+        }
+
+        Ok(ring)
+    }
+
+    pub fn add_key(&mut self, id: KeyId, public_key: &[u8]) -> Result<&mut Self, InvalidKeyring> {
+        if public_key.len() != ED25519_PUBLIC_KEY_LEN {
+            return Err(InvalidKeyring);
+        }
+        self.keys.insert(id, UnparsedPublicKey::new(&signature::ED25519, public_key.to_vec()));
+        Ok(self)
+    }
+
+    /// Removes a key, so that tokens signed with it are no longer accepted.
+    /// Fails if there is no such key.
+    pub fn retire(&mut self, id: KeyId) -> Result<&mut Self, InvalidKeyring> {
+        if self.keys.remove(&id).is_none() {
+            return Err(InvalidKeyring);
+        }
+        Ok(self)
+    }
+}
+
+impl VerifyingKeys for PublicKeyring {
+    fn verify(&self, version: u8, id: KeyId, signed: &[u8], signature: &[u8]) -> Result<(), TokenError> {
+        if version != ED25519_TOKEN_VERSION { return Err(TokenError::UnsupportedVersion(version)) };
+        let key = self.keys.get(&id).ok_or(TokenError::UnknownKey(id))?;
+        key.verify(signed, signature).map_err(|_| TokenError::BadSignature)
+    }
+}
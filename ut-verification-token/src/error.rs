@@ -0,0 +1,71 @@
+use std::fmt;
+
+use crate::{KeyId, ReplayStoreError};
+
+/// Reasons a token can be refused.
+#[derive(Debug)]
+pub enum TokenError {
+    /// The token is not valid url-safe base64
+    MalformedEncoding(base64::DecodeError),
+    /// The token is too short to contain a header and signature
+    TooShort,
+    /// The JWT header is missing, or lacks a valid `kid`
+    MalformedHeader,
+    /// The JWT is signed with an algorithm other than `HS256` or `EdDSA`
+    UnsupportedAlgorithm(String),
+    /// The token was signed with a key that is not in the keyring
+    UnknownKey(KeyId),
+    /// The signature does not match the token's contents, or an encrypted token failed to authenticate
+    BadSignature,
+    /// The token's format version is unknown, or no longer accepted
+    UnsupportedVersion(u8),
+    /// The signature is valid, but the claims could not be deserialized
+    BadClaims(Box<dyn std::error::Error + Send + Sync>),
+    /// The token is past its expiry, older than the allowed maximum age, or issued in the future
+    Expired,
+    /// The token was issued for a different service or service account
+    WrongAccount,
+    /// The token is restricted to audiences that do not include the expected one
+    WrongAudience,
+    /// A disclosure does not match any of the token's commitments, or repeats a field
+    BadDisclosure,
+    /// The token, or every token for its EID issued before it, has been revoked
+    Revoked,
+    /// The token has already been redeemed
+    Replayed,
+    /// The replay store could not be consulted
+    ReplayStore(ReplayStoreError),
+}
+
+impl fmt::Display for TokenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MalformedEncoding(err) => write!(f, "token is not valid base64: {}", err),
+            Self::TooShort => write!(f, "token is truncated"),
+            Self::MalformedHeader => write!(f, "token header is invalid"),
+            Self::UnsupportedAlgorithm(alg) => write!(f, "token signing algorithm {} is not supported", alg),
+            Self::UnknownKey(id) => write!(f, "token was signed with unknown key {}", id),
+            Self::BadSignature => write!(f, "token signature is invalid"),
+            Self::UnsupportedVersion(version) => write!(f, "token format version {} is not supported", version),
+            Self::BadClaims(err) => write!(f, "token claims are invalid: {}", err),
+            Self::Expired => write!(f, "token has expired"),
+            Self::WrongAccount => write!(f, "token was issued for a different account"),
+            Self::WrongAudience => write!(f, "token was issued for a different audience"),
+            Self::BadDisclosure => write!(f, "token disclosure does not match its claims"),
+            Self::Revoked => write!(f, "token has been revoked"),
+            Self::Replayed => write!(f, "token has already been used"),
+            Self::ReplayStore(err) => write!(f, "could not check whether token was already used: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for TokenError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::MalformedEncoding(err) => Some(err),
+            Self::BadClaims(err) => Some(&**err),
+            Self::ReplayStore(err) => Some(&**err),
+            _ => None,
+        }
+    }
+}
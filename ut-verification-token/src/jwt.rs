@@ -0,0 +1,119 @@
+//! Standard compact JWTs carrying the same claims as our own token format.
+//!
+//! Tokens signed with a shared [`Keyring`](crate::Keyring) use `HS256`, and those signed
+//! with an Ed25519 [`SigningKey`](crate::SigningKey) use `EdDSA`, so they can be checked
+//! with any off-the-shelf JWT library. The key id is carried in the `kid` header.
+
+use serde::{Deserialize, Serialize};
+
+use crate::validation::Validation;
+use crate::{KeyId, SigningKeys, TokenError, VerifiedClaims, VerifyingKeys, ED25519_TOKEN_VERSION, TOKEN_VERSION};
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    alg: String,
+    #[serde(default)]
+    typ: Option<String>,
+    #[serde(default)]
+    kid: Option<String>,
+}
+
+fn algorithm(version: u8) -> Option<&'static str> {
+    match version {
+        TOKEN_VERSION => Some("HS256"),
+        ED25519_TOKEN_VERSION => Some("EdDSA"),
+        _ => None,
+    }
+}
+
+fn encode_segment(data: &[u8]) -> String {
+    base64::encode_config(data, base64::URL_SAFE_NO_PAD)
+}
+
+fn decode_segment(segment: &str) -> Result<Vec<u8>, TokenError> {
+    base64::decode_config(segment, base64::URL_SAFE_NO_PAD).map_err(TokenError::MalformedEncoding)
+}
+
+/// Signs a JWT with the active key of `keys`.
+pub fn encode_jwt<K: SigningKeys + ?Sized>(claims: &VerifiedClaims, keys: &K) -> String {
+    let header = Header {
+        alg: algorithm(keys.version()).expect("keys cannot sign JWTs").to_owned(),
+        typ: Some("JWT".to_owned()),
+        kid: Some(keys.key_id().to_string()),
+    };
+
+    let mut token = encode_segment(&serde_json::to_vec(&header).unwrap());
+    token.push('.');
+    token.push_str(&encode_segment(&serde_json::to_vec(claims).unwrap()));
+
+    let signature = keys.sign(token.as_bytes());
+    token.push('.');
+    token.push_str(&encode_segment(&signature));
+
+    token
+}
+
+/// Checks the signature of a JWT, without looking at its timestamps.
+pub fn decode_jwt<K: VerifyingKeys + ?Sized>(token: &str, keys: &K) -> Result<VerifiedClaims, TokenError> {
+    open_jwt(token, keys).map(|(_, claims)| claims)
+}
+
+/// Decodes a JWT, rejecting it unless its claims pass `validation`.
+pub fn decode_jwt_with<K: VerifyingKeys + ?Sized>(
+    token: &str,
+    keys: &K,
+    validation: &Validation,
+) -> Result<VerifiedClaims, TokenError> {
+    let (version, claims) = open_jwt(token, keys)?;
+    validation.check(version, &claims, None)?;
+    Ok(claims)
+}
+
+fn open_jwt<K: VerifyingKeys + ?Sized>(token: &str, keys: &K) -> Result<(u8, VerifiedClaims), TokenError> {
+    let (signed, signature) = token.rsplit_once('.').ok_or(TokenError::MalformedHeader)?;
+    let (header, claims) = signed.split_once('.').ok_or(TokenError::MalformedHeader)?;
+
+    let header: Header = serde_json::from_slice(&decode_segment(header)?).map_err(|_| TokenError::MalformedHeader)?;
+    let version = match header.alg.as_str() {
+        "HS256" => TOKEN_VERSION,
+        "EdDSA" => ED25519_TOKEN_VERSION,
+        _ => return Err(TokenError::UnsupportedAlgorithm(header.alg)),
+    };
+    let id: KeyId = match header.kid {
+        Some(kid) => kid.parse().map_err(|_| TokenError::MalformedHeader)?,
+        None => return Err(TokenError::MalformedHeader),
+    };
+
+    keys.verify(version, id, signed.as_bytes(), &decode_segment(signature)?)?;
+
+    let claims = serde_json::from_slice(&decode_segment(claims)?).map_err(|err| TokenError::BadClaims(err.into()))?;
+    Ok((version, claims))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::{claims, FixedClock};
+    use crate::{Keyring, PublicKeyring, SigningKey};
+
+    #[test]
+    fn round_trips_jwts() {
+        let keys = Keyring::new(4, b"secret");
+        let token = encode_jwt(&claims(1000), &keys);
+        assert_eq!(token.split('.').count(), 3);
+        assert!(token.starts_with(&base64::encode_config(r#"{"alg":"HS256","typ":"JWT","kid":"4"}"#, base64::URL_SAFE_NO_PAD)));
+
+        let decoded = decode_jwt_with(&token, &keys, &Validation::new(&FixedClock(1000)).account("discord", "1234")).unwrap();
+        assert_eq!(decoded.major, vec!["Computer Science"]);
+        assert!(matches!(decode_jwt(&token, &Keyring::new(4, b"other")), Err(TokenError::BadSignature)));
+
+        let rng = ring::rand::SystemRandom::new();
+        let pkcs8 = ring::signature::Ed25519KeyPair::generate_pkcs8(&rng).unwrap();
+        let signing_key = SigningKey::from_pkcs8(5, pkcs8.as_ref()).unwrap();
+        let token = encode_jwt(&claims(1000), &signing_key);
+        assert!(decode_jwt(&token, &PublicKeyring::new(5, signing_key.public_key()).unwrap()).is_ok());
+
+        let none_alg = format!("{}.{}.", base64::encode_config(r#"{"alg":"none","kid":"4"}"#, base64::URL_SAFE_NO_PAD), token.split('.').nth(1).unwrap());
+        assert!(matches!(decode_jwt(&none_alg, &keys), Err(TokenError::UnsupportedAlgorithm(_))));
+    }
+}
@@ -0,0 +1,143 @@
+//! Set of HMAC keys used to sign and verify tokens.
+//!
+//! Tokens are signed with the active key, and carry its id so that they can be
+//! verified against any key still in the ring. To rotate, add the new key everywhere
+//! tokens are verified, then make it active where tokens are minted, and retire the
+//! old key once every link signed with it has expired.
+//!
+//! ```
+//! use utv_token::Keyring;
+//!
+//! let mut keys = Keyring::new(1, b"old secret");
+//! keys.add_key(2, b"new secret");
+//! keys.set_active(2).unwrap();
+//! keys.retire(1).unwrap();
+//! ```
+
+use std::collections::BTreeMap;
+
+use ring::hmac;
+
+use crate::{SigningKeys, TokenError, VerifyingKeys, TOKEN_VERSION};
+
+pub type KeyId = u8;
+
+pub struct Keyring {
+    active: KeyId,
+    keys: BTreeMap<KeyId, hmac::Key>,
+    /// Key that signed unversioned tokens, and the unix time after which they are refused
+    legacy: Option<(KeyId, u64)>,
+}
+
+impl Keyring {
+    /// Creates a keyring containing a single key, which is used for signing.
+    pub fn new(id: KeyId, key: &[u8]) -> Self {
+        let mut keys = BTreeMap::new();
+        keys.insert(id, hmac::Key::new(hmac::HMAC_SHA256, key));
+        Keyring { active: id, keys, legacy: None }
+    }
+
+    /// Parses a comma-separated list of `id:key` pairs, with keys encoded as url-safe base64.
+    pub fn parse(keys: &str, active: KeyId) -> Result<Self, InvalidKeyring> {
+        let mut ring = Keyring {
+            active,
+            keys: BTreeMap::new(),
+            legacy: None,
+        };
+
+        for (id, key) in parse_keys(keys)? {
+            ring.add_key(id, &key);
+        }
+
+        if !ring.keys.contains_key(&active) {
+            return Err(InvalidKeyring);
+        }
+
+        Ok(ring)
+    }
+
+    /// Adds a key that tokens may be verified against, replacing any key with the same id.
+    pub fn add_key(&mut self, id: KeyId, key: &[u8]) -> &mut Self {
+        self.keys.insert(id, hmac::Key::new(hmac::HMAC_SHA256, key));
+        self
+    }
+
+    /// Signs new tokens with the key `id`, which must already be in the ring.
+    pub fn set_active(&mut self, id: KeyId) -> Result<&mut Self, InvalidKeyring> {
+        if !self.keys.contains_key(&id) {
+            return Err(InvalidKeyring);
+        }
+        self.active = id;
+        Ok(self)
+    }
+
+    /// Removes a key, so that tokens signed with it are no longer accepted.
+    /// Fails if there is no such key, or if it is the active key.
+    pub fn retire(&mut self, id: KeyId) -> Result<&mut Self, InvalidKeyring> {
+        if id == self.active || self.keys.remove(&id).is_none() {
+            return Err(InvalidKeyring);
+        }
+        Ok(self)
+    }
+
+    /// Accepts unversioned tokens signed with the key `id`, until the unix time `deadline`.
+    ///
+    /// Unversioned tokens carry no expiry, so the deadline should leave just enough time
+    /// for links sent before the upgrade to be used.
+    pub fn accept_legacy(&mut self, id: KeyId, deadline: u64) -> &mut Self {
+        self.legacy = Some((id, deadline));
+        self
+    }
+
+    pub fn active_id(&self) -> KeyId {
+        self.active
+    }
+
+    pub fn contains(&self, id: KeyId) -> bool {
+        self.keys.contains_key(&id)
+    }
+}
+
+impl SigningKeys for Keyring {
+    fn version(&self) -> u8 {
+        TOKEN_VERSION
+    }
+
+    fn key_id(&self) -> KeyId {
+        self.active
+    }
+
+    fn sign(&self, data: &[u8]) -> Vec<u8> {
+        hmac::sign(&self.keys[&self.active], data).as_ref().to_vec()
+    }
+}
+
+impl VerifyingKeys for Keyring {
+    fn verify(&self, version: u8, id: KeyId, signed: &[u8], signature: &[u8]) -> Result<(), TokenError> {
+        if version != TOKEN_VERSION { return Err(TokenError::UnsupportedVersion(version)) };
+        let key = self.keys.get(&id).ok_or(TokenError::UnknownKey(id))?;
+        hmac::verify(key, signed, signature).map_err(|_| TokenError::BadSignature)
+    }
+
+    fn legacy(&self) -> Option<(KeyId, u64)> {
+        self.legacy
+    }
+}
+
+/// Parses a comma-separated list of `id:key` pairs, with keys encoded as url-safe base64.
+pub(crate) fn parse_keys(keys: &str) -> Result<Vec<(KeyId, Vec<u8>)>, InvalidKeyring> {
+    keys.split(',')
+        .map(str::trim)
+        .filter(|e| !e.is_empty())
+        .map(|entry| {
+            let (id, key) = entry.split_once(':').ok_or(InvalidKeyring)?;
+            let id = id.trim().parse().map_err(|_| InvalidKeyring)?;
+            let key = base64::decode_config(key.trim(), base64::URL_SAFE_NO_PAD)
+                .map_err(|_| InvalidKeyring)?;
+            Ok((id, key))
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+pub struct InvalidKeyring;
@@ -1,37 +1,520 @@
-use ring::hmac;
+use std::time::Duration;
 
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug)]
+mod affiliation;
+mod diff;
+mod disclosure;
+mod ed25519;
+mod error;
+mod jwt;
+mod keyring;
+mod replay;
+mod revocation;
+mod sealed;
+mod validation;
+#[cfg(any(target_arch = "wasm32", test))]
+mod wasm;
+
+pub use affiliation::Affiliation;
+pub use diff::{ClaimsDiff, FieldDiff};
+pub use disclosure::{decode_selective_token, disclose, encode_selective_token, DisclosedClaims, DISCLOSABLE_FIELDS};
+pub use ed25519::{PublicKeyring, SigningKey};
+pub use error::TokenError;
+pub use jwt::{decode_jwt, decode_jwt_with, encode_jwt};
+pub use keyring::{InvalidKeyring, KeyId, Keyring};
+pub use replay::{MemoryReplayStore, ReplayStore, ReplayStoreError};
+#[cfg(feature = "sled")]
+pub use replay::SledReplayStore;
+pub use revocation::{InvalidRevocationList, RevocationList, Revocations};
+pub use sealed::{open_sealed_token, open_sealed_token_with, seal_token, SealingKeyring};
+pub use validation::Validation;
+
+/// How long an emailed verification link stays valid.
+pub const TOKEN_LIFETIME: Duration = Duration::from_secs(15 * 60);
+
+/// Leading byte of tokens signed with a shared HMAC key.
+pub const TOKEN_VERSION: u8 = 1;
+
+/// Leading byte of tokens signed with an Ed25519 private key.
+pub const ED25519_TOKEN_VERSION: u8 = 2;
+
+/// Leading byte of tokens whose claims are encrypted.
+pub const SEALED_TOKEN_VERSION: u8 = 3;
+
+/// Claims are encoded as a msgpack map keyed by field name. Unknown fields are ignored
+/// when decoding, so new fields can be added as long as they have a `#[serde(default)]`,
+/// and go at the end so that claims encoded as an array still line up.
+///
+/// Build claims with `..Default::default()` for the fields that don't apply, so that adding
+/// a field doesn't mean updating every literal.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct VerifiedClaims {
     pub encrypted_eid: Vec<u8>,
+    #[serde(default)]
     pub major: Vec<String>,
+    #[serde(default)]
     pub school: Vec<String>,
-    pub affiliation: Vec<String>,
+    #[serde(default)]
+    pub affiliation: Vec<Affiliation>,
+    /// Issued-at, in seconds since the unix epoch
+    pub iat: u64,
+    /// Expiry, in seconds since the unix epoch
+    pub exp: u64,
+    /// Service the token may be redeemed on, e.g. `discord`
+    pub service: String,
+    /// Account on `service` the token may be redeemed for
+    pub service_id: String,
+    /// Unique id of the token, so that it can only be redeemed once
+    pub jti: String,
+    /// Audiences the token is restricted to, such as guild ids or service names. Empty when
+    /// any consumer may accept it.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aud: Vec<String>,
+    /// Commitments to selectively disclosed fields, see [`disclose`]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sd: Vec<Vec<u8>>,
+    /// Year of study, e.g. `senior`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub classification: Vec<String>,
+    /// Department, for faculty and staff
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub department: Vec<String>,
+    /// Job title, for faculty and staff
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub title: Vec<String>,
+    /// Expected graduation term, e.g. `Spring 2025`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub graduation: Vec<String>,
+    /// Institution that verified the person, e.g. `utexas`. Empty for tokens issued before
+    /// there was more than one, which were all UT Austin.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub institution: String,
+}
+
+/// Claims as carried by tokens issued before the format was versioned.
+#[derive(Deserialize)]
+struct LegacyClaims {
+    encrypted_eid: Vec<u8>,
+    major: Vec<String>,
+    school: Vec<String>,
+    affiliation: Vec<Affiliation>,
+}
+
+/// Source of the current time, in seconds since the unix epoch.
+pub trait Clock {
+    fn now(&self) -> u64;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    #[cfg(not(target_arch = "wasm32"))]
+    fn now(&self) -> u64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0)
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn now(&self) -> u64 {
+        (wasm::now() / 1000.0) as u64
+    }
+}
+
+/// Generates a random token id for the `jti` claim.
+pub fn new_token_id() -> String {
+    let mut id = [0; 16];
+    ring::rand::SecureRandom::fill(&ring::rand::SystemRandom::new(), &mut id).unwrap();
+    base64::encode_config(id, base64::URL_SAFE_NO_PAD)
+}
+
+/// Keys that tokens are signed with.
+pub trait SigningKeys {
+    /// Format version of the tokens these keys produce.
+    fn version(&self) -> u8;
+    /// Id of the key new tokens are signed with.
+    fn key_id(&self) -> KeyId;
+    fn sign(&self, data: &[u8]) -> Vec<u8>;
 }
 
-pub fn encode_token(claims: &VerifiedClaims, shared_key: &[u8]) -> String {
-    let mut data = rmp_serde::to_vec(claims).unwrap();
+/// Keys that token signatures are checked against.
+pub trait VerifyingKeys {
+    /// Checks `signature` over `signed`, for a token in format `version` signed with key `id`.
+    fn verify(&self, version: u8, id: KeyId, signed: &[u8], signature: &[u8]) -> Result<(), TokenError>;
 
-    let hmac_key = hmac::Key::new(ring::hmac::HMAC_SHA256, shared_key);
-    let hmac_tag = hmac::sign(&hmac_key, &data[..]);
+    /// Key that signed unversioned tokens, and the unix time after which they are refused.
+    fn legacy(&self) -> Option<(KeyId, u64)> {
+        None
+    }
+}
+
+/// Signs a token with the active key of `keys`.
+///
+/// Tokens are laid out as `version || key id || msgpack claims || signature`.
+pub fn encode_token<K: SigningKeys + ?Sized>(claims: &VerifiedClaims, keys: &K) -> String {
+    let mut data = vec![keys.version(), keys.key_id()];
+    rmp_serde::encode::write_named(&mut data, claims).unwrap();
+
+    let signature = keys.sign(&data[..]);
 
-    data.extend_from_slice(hmac_tag.as_ref());
+    data.extend_from_slice(&signature);
 
     base64::encode_config(data, base64::URL_SAFE_NO_PAD)
 }
 
+/// Checks the signature of a token, without looking at its timestamps.
+///
+/// Unversioned tokens are accepted while the keyring has a legacy key, and carry no timestamps.
+pub fn decode_token<K: VerifyingKeys + ?Sized>(token: &str, keys: &K) -> Result<VerifiedClaims, TokenError> {
+    open_token(token, keys).map(|(_, claims)| claims)
+}
+
+/// Verifies a token and returns its format version (0 for unversioned tokens) along with its claims.
+fn open_token<K: VerifyingKeys + ?Sized>(token: &str, keys: &K) -> Result<(u8, VerifiedClaims), TokenError> {
+    let data = base64::decode_config(token, base64::URL_SAFE_NO_PAD).map_err(TokenError::MalformedEncoding)?;
+    if data.is_empty() { return Err(TokenError::TooShort) };
+
+    match data[0] {
+        version @ (TOKEN_VERSION | ED25519_TOKEN_VERSION) => {
+            let signature_len = if version == TOKEN_VERSION { 32 } else { 64 };
+            if data.len() <= 2 + signature_len { return Err(TokenError::TooShort) };
+
+            let (signed, signature) = data.split_at(data.len() - signature_len);
+            keys.verify(version, signed[1], signed, signature)?;
+
+            let claims = rmp_serde::from_read(&signed[2..]).map_err(|err| TokenError::BadClaims(err.into()))?;
+            Ok((version, claims))
+        }
+        // unversioned tokens start directly with a msgpack fixarray
+        0x90..=0x9f => {
+            if data.len() <= 32 { return Err(TokenError::TooShort) };
+
+            let (legacy_key, _) = keys.legacy().ok_or(TokenError::UnsupportedVersion(0))?;
+            let (signed, hmac_tag) = data.split_at(data.len() - 32);
+            keys.verify(TOKEN_VERSION, legacy_key, signed, hmac_tag)?;
+
+            let legacy: LegacyClaims = rmp_serde::from_read(signed).map_err(|err| TokenError::BadClaims(err.into()))?;
+            let claims = VerifiedClaims {
+                encrypted_eid: legacy.encrypted_eid,
+                major: legacy.major,
+                school: legacy.school,
+                affiliation: legacy.affiliation,
+                ..Default::default()
+            };
+            Ok((0, claims))
+        }
+        version => Err(TokenError::UnsupportedVersion(version)),
+    }
+}
+
+/// What can be read from a token without any keys. None of it should be trusted.
 #[derive(Debug)]
-pub struct InvalidToken;
+pub struct TokenInfo {
+    /// Format version, or 0 for unversioned tokens
+    pub version: u8,
+    pub key_id: Option<KeyId>,
+    /// Claims, unless the token is encrypted
+    pub claims: Option<VerifiedClaims>,
+}
+
+/// Accepts any signature, so that tokens can be inspected.
+struct Unverified;
+
+impl VerifyingKeys for Unverified {
+    fn verify(&self, _: u8, _: KeyId, _: &[u8], _: &[u8]) -> Result<(), TokenError> {
+        Ok(())
+    }
+
+    fn legacy(&self) -> Option<(KeyId, u64)> {
+        Some((0, u64::MAX))
+    }
+}
+
+/// Reads a token's header and claims WITHOUT checking its signature, for debugging.
+pub fn inspect_token(token: &str) -> Result<TokenInfo, TokenError> {
+    let data = base64::decode_config(token, base64::URL_SAFE_NO_PAD).map_err(TokenError::MalformedEncoding)?;
+
+    match data.first() {
+        Some(&SEALED_TOKEN_VERSION) if data.len() > 1 => Ok(TokenInfo {
+            version: SEALED_TOKEN_VERSION,
+            key_id: Some(data[1]),
+            claims: None,
+        }),
+        _ => {
+            let (version, claims) = open_token(token, &Unverified)?;
+            Ok(TokenInfo {
+                version,
+                key_id: if version == 0 { None } else { Some(data[1]) },
+                claims: Some(claims),
+            })
+        }
+    }
+}
+
+/// Decodes a token, rejecting it if it has expired or was issued more than `max_age` ago.
+///
+/// Unversioned tokens have no timestamps, and are only accepted until the keyring's legacy deadline.
+pub fn decode_fresh_token<K: VerifyingKeys + ?Sized>(
+    token: &str,
+    keys: &K,
+    clock: &impl Clock,
+    max_age: Duration,
+) -> Result<VerifiedClaims, TokenError> {
+    decode_token_with(token, keys, &Validation::new(clock).max_age(max_age))
+}
+
+/// Decodes a token, rejecting it unless its claims pass `validation`.
+///
+/// ```no_run
+/// # use utv_token::*;
+/// # let (token, keys, discord_id) = ("", Keyring::new(0, b""), "");
+/// let claims = decode_token_with(token, &keys, &Validation::new(&SystemClock).account("discord", discord_id));
+/// ```
+pub fn decode_token_with<K: VerifyingKeys + ?Sized>(
+    token: &str,
+    keys: &K,
+    validation: &Validation,
+) -> Result<VerifiedClaims, TokenError> {
+    let (version, claims) = open_token(token, keys)?;
+    validation.check(version, &claims, keys.legacy().map(|(_, deadline)| deadline))?;
+    Ok(claims)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    pub(crate) struct FixedClock(pub(crate) u64);
+
+    impl Clock for FixedClock {
+        fn now(&self) -> u64 {
+            self.0
+        }
+    }
+
+    pub(crate) fn claims(iat: u64) -> VerifiedClaims {
+        VerifiedClaims {
+            encrypted_eid: vec![1, 2, 3],
+            major: vec!["Computer Science".to_owned()],
+            school: vec!["College of Natural Sciences".to_owned()],
+            affiliation: vec![Affiliation::Student],
+            classification: vec!["senior".to_owned()],
+            graduation: vec!["Spring 2025".to_owned()],
+            institution: "utexas".to_owned(),
+            iat,
+            exp: iat + TOKEN_LIFETIME.as_secs(),
+            service: "discord".to_owned(),
+            service_id: "1234".to_owned(),
+            jti: new_token_id(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn rejects_stale_tokens() {
+        let keys = Keyring::new(0, b"secret");
+        let token = encode_token(&claims(1000), &keys);
+
+        assert!(decode_fresh_token(&token, &keys, &FixedClock(1000 + 60), TOKEN_LIFETIME).is_ok());
+        assert!(matches!(
+            decode_fresh_token(&token, &keys, &FixedClock(1000 + 15 * 60), TOKEN_LIFETIME),
+            Err(TokenError::Expired)
+        ));
+        assert!(matches!(
+            decode_fresh_token(&token, &keys, &FixedClock(1000 + 120), Duration::from_secs(60)),
+            Err(TokenError::Expired)
+        ));
+        assert!(matches!(
+            decode_fresh_token(&token, &Keyring::new(0, b"other"), &FixedClock(1000), TOKEN_LIFETIME),
+            Err(TokenError::BadSignature)
+        ));
+    }
+
+    #[test]
+    fn rejects_tokens_for_other_accounts() {
+        let keys = Keyring::new(0, b"secret");
+        let token = encode_token(&claims(1000), &keys);
+        let clock = FixedClock(1000);
+
+        assert!(decode_token_with(&token, &keys, &Validation::new(&clock).account("discord", "1234")).is_ok());
+        assert!(matches!(
+            decode_token_with(&token, &keys, &Validation::new(&clock).account("discord", "5678")),
+            Err(TokenError::WrongAccount)
+        ));
+        assert!(matches!(
+            decode_token_with(&token, &keys, &Validation::new(&clock).account("slack", "1234")),
+            Err(TokenError::WrongAccount)
+        ));
+    }
+
+    #[test]
+    fn rejects_tokens_for_other_audiences() {
+        let keys = Keyring::new(0, b"secret");
+        let clock = FixedClock(1000);
+        let mut restricted = claims(1000);
+        restricted.aud = vec!["guild-1".to_owned(), "guild-2".to_owned()];
+        let token = encode_token(&restricted, &keys);
+
+        assert!(decode_token_with(&token, &keys, &Validation::new(&clock).audience("guild-2")).is_ok());
+        assert!(matches!(
+            decode_token_with(&token, &keys, &Validation::new(&clock).audience("guild-3")),
+            Err(TokenError::WrongAudience)
+        ));
+
+        // tokens without an audience are valid for every one
+        let unrestricted = encode_token(&claims(1000), &keys);
+        assert!(decode_token_with(&unrestricted, &keys, &Validation::new(&clock)).is_ok());
+        assert!(decode_token_with(&unrestricted, &keys, &Validation::new(&clock).audience("guild-1")).is_ok());
+    }
+
+    #[test]
+    fn accepts_each_token_once() {
+        let keys = Keyring::new(0, b"secret");
+        let token = encode_token(&claims(1000), &keys);
+        let replays = MemoryReplayStore::new();
+        let validation = Validation::new(&FixedClock(1000)).replay_store(&replays);
+
+        // a token refused for another reason is not used up
+        assert!(decode_token_with(&token, &keys, &Validation::new(&FixedClock(1000)).account("discord", "5678").replay_store(&replays)).is_err());
+
+        assert!(decode_token_with(&token, &keys, &validation).is_ok());
+        assert!(matches!(decode_token_with(&token, &keys, &validation), Err(TokenError::Replayed)));
+        assert!(decode_token_with(&encode_token(&claims(1000), &keys), &keys, &validation).is_ok());
+    }
+
+    #[test]
+    fn verifies_against_any_key_in_ring() {
+        let mut keys = Keyring::new(1, b"old");
+        let old_token = encode_token(&claims(1000), &keys);
+
+        keys.add_key(2, b"new").set_active(2).unwrap();
+        let new_token = encode_token(&claims(1000), &keys);
+
+        assert!(decode_token(&old_token, &keys).is_ok());
+        assert!(decode_token(&new_token, &keys).is_ok());
+        assert!(matches!(decode_token(&new_token, &Keyring::new(1, b"old")), Err(TokenError::UnknownKey(2))));
+
+        keys.retire(1).unwrap();
+        assert!(matches!(decode_token(&old_token, &keys), Err(TokenError::UnknownKey(1))));
+        assert!(decode_token(&new_token, &keys).is_ok());
+
+        // a mistyped rotation is reported rather than ignored
+        assert!(keys.set_active(3).is_err());
+        assert!(keys.retire(1).is_err());
+        assert!(keys.retire(2).is_err());
+        assert_eq!(keys.active_id(), 2);
+    }
+
+    #[test]
+    fn verifies_ed25519_tokens_with_public_key() {
+        let rng = ring::rand::SystemRandom::new();
+        let pkcs8 = ring::signature::Ed25519KeyPair::generate_pkcs8(&rng).unwrap();
+        let signing_key = SigningKey::from_pkcs8(3, pkcs8.as_ref()).unwrap();
+        let mut public_keys = PublicKeyring::new(3, signing_key.public_key()).unwrap();
+
+        let token = encode_token(&claims(1000), &signing_key);
+        assert!(decode_fresh_token(&token, &public_keys, &FixedClock(1000), TOKEN_LIFETIME).is_ok());
+
+        // an hmac keyring cannot vouch for an ed25519 token, or the other way around
+        assert!(matches!(decode_token(&token, &Keyring::new(3, b"secret")), Err(TokenError::UnsupportedVersion(2))));
+        let hmac_token = encode_token(&claims(1000), &Keyring::new(3, b"secret"));
+        assert!(matches!(decode_token(&hmac_token, &public_keys), Err(TokenError::UnsupportedVersion(1))));
+
+        // keys of the wrong length are refused up front, rather than failing every signature
+        assert!(public_keys.add_key(4, b"secret").is_err());
+        assert!(PublicKeyring::parse("3:c2VjcmV0").is_err());
+        assert!(public_keys.retire(4).is_err());
+        public_keys.retire(3).unwrap();
+        assert!(matches!(decode_token(&token, &public_keys), Err(TokenError::UnknownKey(3))));
+    }
+
+    #[test]
+    fn decodes_claims_by_field_name() {
+        let keys = Keyring::new(0, b"secret");
+        let sign = |payload: Vec<u8>| {
+            let mut data = vec![TOKEN_VERSION, 0];
+            data.extend(payload);
+            let tag = ring::hmac::sign(&ring::hmac::Key::new(ring::hmac::HMAC_SHA256, b"secret"), &data);
+            data.extend_from_slice(tag.as_ref());
+            base64::encode_config(data, base64::URL_SAFE_NO_PAD)
+        };
+
+        // from a newer issuer: an extra field, and no major or school
+        #[derive(Serialize)]
+        struct Newer<'a> {
+            cohort: &'a str,
+            encrypted_eid: Vec<u8>,
+            affiliation: Vec<&'a str>,
+            iat: u64,
+            exp: u64,
+            service: &'a str,
+            service_id: &'a str,
+            jti: &'a str,
+        }
+        let newer = Newer {
+            cohort: "2025",
+            encrypted_eid: vec![1],
+            affiliation: vec![" Student", " visiting scholar "],
+            iat: 1000,
+            exp: 2000,
+            service: "discord",
+            service_id: "1234",
+            jti: "a",
+        };
+        let decoded = decode_token(&sign(rmp_serde::to_vec_named(&newer).unwrap()), &keys).unwrap();
+        assert_eq!(decoded.affiliation, vec![Affiliation::Student, Affiliation::Other("visiting scholar".to_owned())]);
+        assert!(decoded.major.is_empty() && decoded.school.is_empty());
+
+        // array-encoded claims, as issued before claims were keyed by name
+        let old = claims(1000);
+        let array = (&old.encrypted_eid, &old.major, &old.school, &old.affiliation, old.iat, old.exp, &old.service, &old.service_id, &old.jti);
+        let decoded = decode_token(&sign(rmp_serde::to_vec(&array).unwrap()), &keys).unwrap();
+        assert_eq!(decoded.major, vec!["Computer Science"]);
+        assert_eq!(decoded.jti, old.jti);
+    }
+
+    #[test]
+    fn accepts_unversioned_tokens_during_transition() {
+        #[derive(Serialize)]
+        struct Unversioned(Vec<u8>, Vec<String>, Vec<String>, Vec<String>);
+
+        let mut data = rmp_serde::to_vec(&Unversioned(vec![1, 2, 3], vec![], vec![], vec!["student".to_owned()])).unwrap();
+        let tag = ring::hmac::sign(&ring::hmac::Key::new(ring::hmac::HMAC_SHA256, b"secret"), &data);
+        data.extend_from_slice(tag.as_ref());
+        let token = base64::encode_config(data, base64::URL_SAFE_NO_PAD);
+
+        let mut keys = Keyring::new(0, b"secret");
+        assert!(matches!(decode_token(&token, &keys), Err(TokenError::UnsupportedVersion(0))));
+
+        keys.accept_legacy(0, 2000);
+        assert_eq!(decode_token(&token, &keys).unwrap().affiliation, vec![Affiliation::Student]);
+        assert!(decode_fresh_token(&token, &keys, &FixedClock(1000), TOKEN_LIFETIME).is_ok());
+        assert!(matches!(
+            decode_fresh_token(&token, &keys, &FixedClock(2000), TOKEN_LIFETIME),
+            Err(TokenError::Expired)
+        ));
+    }
 
-pub fn decode_token(token: &str, shared_key: &[u8]) -> Result<VerifiedClaims, InvalidToken> {
-    let hmac_key = hmac::Key::new(ring::hmac::HMAC_SHA256, shared_key);
+    #[test]
+    fn reports_why_a_token_was_refused() {
+        let keys = Keyring::new(0, b"secret");
+        let token = encode_token(&claims(1000), &keys);
 
-    let data = base64::decode_config(token, base64::URL_SAFE_NO_PAD).map_err(|_| InvalidToken)?;
-    if data.len() <= 32 { return Err(InvalidToken) };
+        assert!(matches!(decode_token("not base64!", &keys), Err(TokenError::MalformedEncoding(_))));
+        assert!(matches!(decode_token(&token[..20], &keys), Err(TokenError::TooShort)));
+        assert!(matches!(decode_token(&format!("_{}", &token[1..]), &keys), Err(TokenError::UnsupportedVersion(_))));
 
-    let (claims_raw, hmac_tag) = data.split_at(data.len() - 32);
-    hmac::verify(&hmac_key, claims_raw, hmac_tag).map_err(|_| InvalidToken)?;
+        let mut data = base64::decode_config(&token, base64::URL_SAFE_NO_PAD).unwrap();
+        data[5] ^= 1;
+        let tampered = base64::encode_config(&data, base64::URL_SAFE_NO_PAD);
+        assert!(matches!(decode_token(&tampered, &keys), Err(TokenError::BadSignature)));
 
-    rmp_serde::from_read(claims_raw).map_err(|_| InvalidToken)
+        let mut data = vec![TOKEN_VERSION, 0, 0xc1];
+        let tag = ring::hmac::sign(&ring::hmac::Key::new(ring::hmac::HMAC_SHA256, b"secret"), &data);
+        data.extend_from_slice(tag.as_ref());
+        let garbage = base64::encode_config(&data, base64::URL_SAFE_NO_PAD);
+        assert!(matches!(decode_token(&garbage, &keys), Err(TokenError::BadClaims(_))));
+    }
 }
@@ -0,0 +1,77 @@
+//! Records of redeemed tokens, so that each token is only accepted once.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Mutex;
+
+pub type ReplayStoreError = Box<dyn Error + Send + Sync>;
+
+pub trait ReplayStore {
+    /// Marks the token `jti` as redeemed, returning false if it already was.
+    ///
+    /// `exp` is when the token expires, after which it no longer needs to be remembered.
+    fn redeem(&self, jti: &str, exp: u64) -> Result<bool, ReplayStoreError>;
+}
+
+/// Replay store for a single process, which forgets everything on restart.
+#[derive(Default)]
+pub struct MemoryReplayStore {
+    redeemed: Mutex<HashMap<String, u64>>,
+}
+
+impl MemoryReplayStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Forgets tokens that expired before `now`.
+    pub fn purge_expired(&self, now: u64) {
+        self.redeemed.lock().unwrap().retain(|_, exp| *exp >= now);
+    }
+}
+
+impl ReplayStore for MemoryReplayStore {
+    fn redeem(&self, jti: &str, exp: u64) -> Result<bool, ReplayStoreError> {
+        let mut redeemed = self.redeemed.lock().unwrap();
+        if redeemed.contains_key(jti) {
+            return Ok(false);
+        }
+        redeemed.insert(jti.to_owned(), exp);
+        Ok(true)
+    }
+}
+
+/// Replay store persisted in a sled tree, mapping token ids to their expiry.
+#[cfg(feature = "sled")]
+pub struct SledReplayStore {
+    tree: sled::Tree,
+}
+
+#[cfg(feature = "sled")]
+impl SledReplayStore {
+    pub fn new(tree: sled::Tree) -> Self {
+        SledReplayStore { tree }
+    }
+
+    /// Forgets tokens that expired before `now`.
+    pub fn purge_expired(&self, now: u64) -> sled::Result<()> {
+        for entry in self.tree.iter() {
+            let (jti, exp) = entry?;
+            let exp = exp.as_ref().try_into().map(u64::from_be_bytes).unwrap_or(0);
+            if exp < now {
+                self.tree.remove(jti)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "sled")]
+impl ReplayStore for SledReplayStore {
+    fn redeem(&self, jti: &str, exp: u64) -> Result<bool, ReplayStoreError> {
+        let inserted = self
+            .tree
+            .compare_and_swap(jti, None as Option<&[u8]>, Some(&exp.to_be_bytes()[..]))?;
+        Ok(inserted.is_ok())
+    }
+}
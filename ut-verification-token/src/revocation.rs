@@ -0,0 +1,127 @@
+//! Tokens that must no longer be accepted, e.g. because the email was reported as phishing.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{VerifiedClaims, TOKEN_LIFETIME};
+
+/// Leading byte of a serialized [`RevocationList`].
+const REVOCATION_LIST_VERSION: u8 = 1;
+
+pub trait Revocations {
+    /// Whether the token carrying `claims` has been revoked.
+    fn is_revoked(&self, claims: &VerifiedClaims) -> bool;
+}
+
+/// Revoked token ids, and EIDs whose earlier tokens are all revoked.
+///
+/// Serialized as `version || msgpack`, so that ut-verification-server can publish it and
+/// consumers can load it without knowing about each other.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct RevocationList {
+    /// Revoked token ids, mapped to when the token expires
+    tokens: HashMap<String, u64>,
+    /// Encrypted EIDs, mapped to the time before which their tokens are revoked
+    eids: HashMap<Vec<u8>, u64>,
+}
+
+impl RevocationList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Revokes the token `jti`, which is remembered until `exp`.
+    pub fn revoke_token(&mut self, jti: &str, exp: u64) -> &mut Self {
+        self.tokens.insert(jti.to_owned(), exp);
+        self
+    }
+
+    /// Revokes every token for `encrypted_eid` issued before `issued_before`, so that the
+    /// student can still verify again afterwards.
+    pub fn revoke_eid(&mut self, encrypted_eid: &[u8], issued_before: u64) -> &mut Self {
+        let cutoff = self.eids.entry(encrypted_eid.to_vec()).or_insert(0);
+        *cutoff = (*cutoff).max(issued_before);
+        self
+    }
+
+    /// Forgets revoked tokens that expired before `now`, as they are refused anyway, and
+    /// EIDs whose revoked tokens have all expired, since none lives past `TOKEN_LIFETIME`.
+    pub fn purge_expired(&mut self, now: u64) {
+        self.tokens.retain(|_, exp| *exp >= now);
+        self.eids.retain(|_, cutoff| cutoff.saturating_add(TOKEN_LIFETIME.as_secs()) > now);
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = vec![REVOCATION_LIST_VERSION];
+        rmp_serde::encode::write_named(&mut data, self).unwrap();
+        data
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, InvalidRevocationList> {
+        match data.split_first() {
+            Some((&REVOCATION_LIST_VERSION, list)) => rmp_serde::from_read(list).map_err(|_| InvalidRevocationList),
+            _ => Err(InvalidRevocationList),
+        }
+    }
+}
+
+impl Revocations for RevocationList {
+    fn is_revoked(&self, claims: &VerifiedClaims) -> bool {
+        self.tokens.contains_key(&claims.jti)
+            || matches!(self.eids.get(&claims.encrypted_eid), Some(&cutoff) if claims.iat < cutoff)
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidRevocationList;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::{claims, FixedClock};
+    use crate::{decode_token_with, encode_token, Keyring, TokenError, Validation};
+
+    #[test]
+    fn refuses_revoked_tokens() {
+        let keys = Keyring::new(0, b"secret");
+        let first = claims(1000);
+        let second = claims(1100);
+        let mut revocations = RevocationList::new();
+        revocations.revoke_token(&first.jti, first.exp);
+
+        // round trip through the published format
+        let revocations = RevocationList::from_bytes(&revocations.to_bytes()).unwrap();
+        let validation = Validation::new(&FixedClock(1100)).revocations(&revocations);
+        assert!(matches!(decode_token_with(&encode_token(&first, &keys), &keys, &validation), Err(TokenError::Revoked)));
+        assert!(decode_token_with(&encode_token(&second, &keys), &keys, &validation).is_ok());
+
+        let mut revocations = revocations.clone();
+        revocations.revoke_eid(&second.encrypted_eid, 1050);
+        let validation = Validation::new(&FixedClock(1100)).revocations(&revocations);
+        assert!(decode_token_with(&encode_token(&second, &keys), &keys, &validation).is_ok());
+        revocations.revoke_eid(&second.encrypted_eid, 1200);
+        let validation = Validation::new(&FixedClock(1100)).revocations(&revocations);
+        assert!(matches!(decode_token_with(&encode_token(&second, &keys), &keys, &validation), Err(TokenError::Revoked)));
+
+        assert!(RevocationList::from_bytes(b"\x02").is_err());
+    }
+
+    #[test]
+    fn purges_entries_once_their_tokens_expire() {
+        let lifetime = TOKEN_LIFETIME.as_secs();
+        let mut revocations = RevocationList::new();
+        revocations.revoke_token("a", 1100).revoke_eid(&[1], 1000).revoke_eid(&[2], 1500);
+
+        revocations.purge_expired(1100);
+        assert_eq!(revocations.tokens.len(), 1);
+        assert_eq!(revocations.eids.len(), 2);
+
+        revocations.purge_expired(1000 + lifetime);
+        assert!(revocations.tokens.is_empty());
+        assert_eq!(revocations.eids.keys().collect::<Vec<_>>(), vec![&vec![2]]);
+
+        revocations.purge_expired(1500 + lifetime);
+        assert_eq!(revocations, RevocationList::new());
+    }
+}
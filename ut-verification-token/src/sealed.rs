@@ -0,0 +1,152 @@
+//! Encrypted tokens, whose claims can only be read by holders of the key.
+//!
+//! Claims are encrypted with AES-256-GCM-SIV under a random nonce, with the token
+//! header as associated data. Tokens are laid out as
+//! `version || key id || nonce || ciphertext and tag`.
+
+use std::collections::BTreeMap;
+
+use aes_gcm_siv::aead::{Aead, NewAead, Payload};
+use aes_gcm_siv::{Aes256GcmSiv, Key, Nonce};
+use ring::rand::{SecureRandom, SystemRandom};
+
+use crate::keyring::parse_keys;
+use crate::validation::Validation;
+use crate::{InvalidKeyring, KeyId, TokenError, VerifiedClaims, SEALED_TOKEN_VERSION};
+
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+/// Keys that tokens are encrypted with. New tokens use the active key, and
+/// tokens encrypted with any key in the ring can be opened.
+pub struct SealingKeyring {
+    active: KeyId,
+    keys: BTreeMap<KeyId, Aes256GcmSiv>,
+}
+
+impl SealingKeyring {
+    /// Creates a keyring containing a single 32 byte key, which is used for encryption.
+    pub fn new(id: KeyId, key: &[u8]) -> Result<Self, InvalidKeyring> {
+        let mut ring = SealingKeyring {
+            active: id,
+            keys: BTreeMap::new(),
+        };
+        ring.add_key(id, key)?;
+        Ok(ring)
+    }
+
+    /// Parses a comma-separated list of `id:key` pairs, with keys encoded as url-safe base64.
+    pub fn parse(keys: &str, active: KeyId) -> Result<Self, InvalidKeyring> {
+        let mut ring = SealingKeyring {
+            active,
+            keys: BTreeMap::new(),
+        };
+
+        for (id, key) in parse_keys(keys)? {
+            ring.add_key(id, &key)?;
+        }
+
+        if !ring.keys.contains_key(&active) {
+            return Err(InvalidKeyring);
+        }
+
+        Ok(ring)
+    }
+
+    pub fn add_key(&mut self, id: KeyId, key: &[u8]) -> Result<&mut Self, InvalidKeyring> {
+        if key.len() != 32 {
+            return Err(InvalidKeyring);
+        }
+        self.keys.insert(id, Aes256GcmSiv::new(Key::from_slice(key)));
+        Ok(self)
+    }
+
+    /// Encrypts new tokens with the key `id`, which must already be in the ring.
+    pub fn set_active(&mut self, id: KeyId) -> Result<&mut Self, InvalidKeyring> {
+        if !self.keys.contains_key(&id) {
+            return Err(InvalidKeyring);
+        }
+        self.active = id;
+        Ok(self)
+    }
+
+    /// Removes a key, so that tokens encrypted with it can no longer be opened.
+    /// Fails if there is no such key, or if it is the active key.
+    pub fn retire(&mut self, id: KeyId) -> Result<&mut Self, InvalidKeyring> {
+        if id == self.active || self.keys.remove(&id).is_none() {
+            return Err(InvalidKeyring);
+        }
+        Ok(self)
+    }
+}
+
+/// Encrypts a token with the active key of `keys`.
+pub fn seal_token(claims: &VerifiedClaims, keys: &SealingKeyring) -> String {
+    let header = [SEALED_TOKEN_VERSION, keys.active];
+
+    let mut nonce = [0; NONCE_LEN];
+    SystemRandom::new().fill(&mut nonce).unwrap();
+
+    let msg = rmp_serde::to_vec_named(claims).unwrap();
+    let ciphertext = keys.keys[&keys.active]
+        .encrypt(Nonce::from_slice(&nonce), Payload { msg: &msg, aad: &header })
+        .unwrap();
+
+    let mut data = header.to_vec();
+    data.extend_from_slice(&nonce);
+    data.extend_from_slice(&ciphertext);
+
+    base64::encode_config(data, base64::URL_SAFE_NO_PAD)
+}
+
+/// Decrypts a token, without looking at its timestamps.
+pub fn open_sealed_token(token: &str, keys: &SealingKeyring) -> Result<VerifiedClaims, TokenError> {
+    let data = base64::decode_config(token, base64::URL_SAFE_NO_PAD).map_err(TokenError::MalformedEncoding)?;
+    if data.len() <= 2 + NONCE_LEN + TAG_LEN { return Err(TokenError::TooShort) };
+
+    let (header, body) = data.split_at(2);
+    if header[0] != SEALED_TOKEN_VERSION { return Err(TokenError::UnsupportedVersion(header[0])) };
+
+    let cipher = keys.keys.get(&header[1]).ok_or(TokenError::UnknownKey(header[1]))?;
+    let (nonce, ciphertext) = body.split_at(NONCE_LEN);
+    let msg = cipher
+        .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: header })
+        .map_err(|_| TokenError::BadSignature)?;
+
+    rmp_serde::from_read(&msg[..]).map_err(|err| TokenError::BadClaims(err.into()))
+}
+
+/// Decrypts a token, rejecting it unless its claims pass `validation`.
+pub fn open_sealed_token_with(
+    token: &str,
+    keys: &SealingKeyring,
+    validation: &Validation,
+) -> Result<VerifiedClaims, TokenError> {
+    let claims = open_sealed_token(token, keys)?;
+    validation.check(SEALED_TOKEN_VERSION, &claims, None)?;
+    Ok(claims)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::{claims, FixedClock};
+    use crate::Affiliation;
+
+    #[test]
+    fn sealed_tokens_hide_claims() {
+        let keys = SealingKeyring::new(0, &[7; 32]).unwrap();
+        let token = seal_token(&claims(1000), &keys);
+        let data = base64::decode_config(&token, base64::URL_SAFE_NO_PAD).unwrap();
+        assert!(!data.windows(7).any(|w| w == b"student"));
+
+        // random nonces make every token distinct
+        assert_ne!(token, seal_token(&claims(1000), &keys));
+
+        let opened = open_sealed_token_with(&token, &keys, &Validation::new(&FixedClock(1000))).unwrap();
+        assert_eq!(opened.affiliation, vec![Affiliation::Student]);
+
+        let other_keys = SealingKeyring::new(0, &[8; 32]).unwrap();
+        assert!(matches!(open_sealed_token(&token, &other_keys), Err(TokenError::BadSignature)));
+    }
+}
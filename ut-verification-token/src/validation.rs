@@ -0,0 +1,115 @@
+//! Checks applied to a token's claims once its signature has been verified.
+
+use std::time::Duration;
+
+use crate::{Clock, ReplayStore, Revocations, TokenError, VerifiedClaims, TOKEN_LIFETIME};
+
+/// Tolerated drift between the clock that issued a token and the one checking it.
+const CLOCK_SKEW: u64 = 60;
+
+pub struct Validation<'a> {
+    clock: &'a dyn Clock,
+    max_age: Duration,
+    account: Option<(&'a str, &'a str)>,
+    audience: Option<&'a str>,
+    revocations: Option<&'a dyn Revocations>,
+    replay_store: Option<&'a dyn ReplayStore>,
+}
+
+impl<'a> Validation<'a> {
+    /// Accepts tokens that have not expired and are at most `TOKEN_LIFETIME` old.
+    pub fn new(clock: &'a dyn Clock) -> Self {
+        Validation {
+            clock,
+            max_age: TOKEN_LIFETIME,
+            account: None,
+            audience: None,
+            revocations: None,
+            replay_store: None,
+        }
+    }
+
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    /// Only accepts tokens issued for the given account, e.g. `("discord", "[discord id]")`.
+    pub fn account(mut self, service: &'a str, service_id: &'a str) -> Self {
+        self.account = Some((service, service_id));
+        self
+    }
+
+    /// Only accepts tokens whose `aud` includes `audience`, such as a guild id, or that
+    /// have no `aud` and so are not restricted to any audience.
+    ///
+    /// Without this, the audience is not checked at all, so consumers that only want
+    /// verifications meant for them must set it.
+    pub fn audience(mut self, audience: &'a str) -> Self {
+        self.audience = Some(audience);
+        self
+    }
+
+    /// Refuses tokens that `revocations` lists, by token id or encrypted EID.
+    pub fn revocations(mut self, revocations: &'a dyn Revocations) -> Self {
+        self.revocations = Some(revocations);
+        self
+    }
+
+    /// Only accepts each token once, recording it in `store` after every other check passes.
+    ///
+    /// Unversioned tokens carry no `jti`, so they cannot be tracked.
+    pub fn replay_store(mut self, store: &'a dyn ReplayStore) -> Self {
+        self.replay_store = Some(store);
+        self
+    }
+
+    /// Validates the claims of a token in format `version`, where unversioned tokens
+    /// are only accepted before `legacy_deadline`.
+    pub(crate) fn check(
+        &self,
+        version: u8,
+        claims: &VerifiedClaims,
+        legacy_deadline: Option<u64>,
+    ) -> Result<(), TokenError> {
+        let now = self.clock.now();
+
+        if version == 0 {
+            match legacy_deadline {
+                Some(deadline) if now < deadline => {}
+                _ => return Err(TokenError::Expired),
+            }
+        } else if now >= claims.exp
+            || claims.iat > now + CLOCK_SKEW
+            || now.saturating_sub(claims.iat) > self.max_age.as_secs()
+        {
+            return Err(TokenError::Expired);
+        }
+
+        if let Some((service, service_id)) = self.account {
+            if claims.service != service || claims.service_id != service_id {
+                return Err(TokenError::WrongAccount);
+            }
+        }
+
+        if let Some(audience) = self.audience {
+            if !claims.aud.is_empty() && !claims.aud.iter().any(|aud| aud == audience) {
+                return Err(TokenError::WrongAudience);
+            }
+        }
+
+        if let Some(revocations) = self.revocations {
+            if revocations.is_revoked(claims) {
+                return Err(TokenError::Revoked);
+            }
+        }
+
+        if let Some(store) = self.replay_store {
+            if version != 0 && !store.redeem(&claims.jti, claims.exp).map_err(TokenError::ReplayStore)? {
+                return Err(TokenError::Replayed);
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,222 @@
+//! Bindings for the website, built with `wasm-pack build --target nodejs`.
+//!
+//! Claims cross the boundary as JSON, and keys use the same `id:key,...` format as `SHARED_KEYS`.
+//! Everything but the clock also builds natively, so that the bindings can be tested.
+
+#[cfg(target_arch = "wasm32")]
+use wasm_bindgen::prelude::*;
+
+use crate::{Keyring, PublicKeyring, SealingKeyring, SystemClock, Validation, VerifiedClaims, VerifyingKeys};
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+extern "C" {
+    /// Milliseconds since the unix epoch, as `SystemTime` is unavailable in wasm.
+    #[wasm_bindgen(js_namespace = Date)]
+    pub(crate) fn now() -> f64;
+}
+
+/// Errors thrown to JavaScript, as a plain message.
+#[cfg(target_arch = "wasm32")]
+type Error = JsValue;
+#[cfg(not(target_arch = "wasm32"))]
+type Error = String;
+
+fn error(message: impl ToString) -> Error {
+    Error::from(message.to_string())
+}
+
+/// The first id in `keys`, which is used for anything that needs an active key.
+fn first_id(keys: &str) -> Result<u8, Error> {
+    keys.split(',')
+        .find_map(|entry| entry.split(':').next()?.trim().parse().ok())
+        .ok_or_else(|| error("no keys given"))
+}
+
+fn parse_keys(keys: &str, active: Option<u8>) -> Result<Keyring, Error> {
+    let active = match active {
+        Some(id) => id,
+        None => first_id(keys)?,
+    };
+    Keyring::parse(keys, active).map_err(|_| error("invalid keys"))
+}
+
+/// Parses `id:deadline`, the key that signed unversioned tokens and the unix time until
+/// which they are accepted.
+fn parse_legacy(legacy: &str) -> Result<(u8, u64), Error> {
+    let (id, deadline) = legacy.split_once(':').ok_or_else(|| error("invalid legacy key"))?;
+    let id = id.trim().parse().map_err(|_| error("invalid legacy key"))?;
+    let deadline = deadline.trim().parse().map_err(|_| error("invalid legacy deadline"))?;
+    Ok((id, deadline))
+}
+
+/// Signs claims, given as JSON, with the key `active` in `keys`.
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub fn encode_token(claims: &str, keys: &str, active: u8) -> Result<String, Error> {
+    let claims: VerifiedClaims = serde_json::from_str(claims).map_err(error)?;
+    let keys = parse_keys(keys, Some(active))?;
+    Ok(crate::encode_token(&claims, &keys))
+}
+
+/// Checks a token's signature and expiry, that it was issued for `service_id` on `service`
+/// and that it is meant for `audience` when they are given, returning its claims as JSON.
+///
+/// Unversioned tokens are only accepted when `legacy` is given as `id:deadline`, see
+/// [`Keyring::accept_legacy`].
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub fn decode_token(
+    token: &str,
+    keys: &str,
+    service: Option<String>,
+    service_id: Option<String>,
+    audience: Option<String>,
+    legacy: Option<String>,
+) -> Result<String, Error> {
+    let mut keys = parse_keys(keys, None)?;
+    if let Some(legacy) = &legacy {
+        let (id, deadline) = parse_legacy(legacy)?;
+        keys.accept_legacy(id, deadline);
+    }
+    decode_with(token, &keys, service, service_id, audience)
+}
+
+/// Like [`decode_token`], for Ed25519-signed tokens, checked against the `id:public key,...`
+/// in `public_keys`. Unversioned tokens were never signed this way, so are always refused.
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub fn decode_public_token(
+    token: &str,
+    public_keys: &str,
+    service: Option<String>,
+    service_id: Option<String>,
+    audience: Option<String>,
+) -> Result<String, Error> {
+    let keys = PublicKeyring::parse(public_keys).map_err(|_| error("invalid public keys"))?;
+    decode_with(token, &keys, service, service_id, audience)
+}
+
+/// Like [`decode_token`], for tokens encrypted with one of the `id:key,...` in `sealing_keys`,
+/// as issued by ut-verification-server when `SEALING_KEYS` is set.
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub fn open_sealed_token(
+    token: &str,
+    sealing_keys: &str,
+    service: Option<String>,
+    service_id: Option<String>,
+    audience: Option<String>,
+) -> Result<String, Error> {
+    let keys = SealingKeyring::parse(sealing_keys, first_id(sealing_keys)?).map_err(|_| error("invalid sealing keys"))?;
+    let claims = crate::open_sealed_token_with(token, &keys, &validation(&service, &service_id, &audience)).map_err(error)?;
+    Ok(serde_json::to_string(&claims).unwrap())
+}
+
+fn decode_with<K: VerifyingKeys>(
+    token: &str,
+    keys: &K,
+    service: Option<String>,
+    service_id: Option<String>,
+    audience: Option<String>,
+) -> Result<String, Error> {
+    let claims = crate::decode_token_with(token, keys, &validation(&service, &service_id, &audience)).map_err(error)?;
+    Ok(serde_json::to_string(&claims).unwrap())
+}
+
+fn validation<'a>(
+    service: &'a Option<String>,
+    service_id: &'a Option<String>,
+    audience: &'a Option<String>,
+) -> Validation<'a> {
+    let mut validation = Validation::new(&SystemClock);
+    if let (Some(service), Some(service_id)) = (service, service_id) {
+        validation = validation.account(service, service_id);
+    }
+    if let Some(audience) = audience {
+        validation = validation.audience(audience);
+    }
+    validation
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Clock, SigningKey, TOKEN_LIFETIME};
+
+    fn claims_json() -> String {
+        let iat = SystemClock.now();
+        serde_json::json!({
+            "encrypted_eid": [1, 2, 3],
+            "affiliation": ["student"],
+            "iat": iat,
+            "exp": iat + TOKEN_LIFETIME.as_secs(),
+            "service": "discord",
+            "service_id": "1234",
+            "jti": "a",
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn parses_keys_like_shared_keys() {
+        assert_eq!(parse_keys("3:c2VjcmV0, 4:b3RoZXI", None).unwrap().active_id(), 3);
+        assert_eq!(parse_keys("3:c2VjcmV0,4:b3RoZXI", Some(4)).unwrap().active_id(), 4);
+        assert!(parse_keys("", None).is_err());
+        assert!(parse_keys("3:c2VjcmV0", Some(4)).is_err());
+        assert!(parse_keys("3:not base64!", None).is_err());
+    }
+
+    #[test]
+    fn decodes_tokens_encoded_by_the_bindings() {
+        let token = encode_token(&claims_json(), "0:b2xk,1:c2VjcmV0", 1).unwrap();
+
+        let claims = decode_token(&token, "1:c2VjcmV0", Some("discord".to_owned()), Some("1234".to_owned()), None, None).unwrap();
+        assert!(claims.contains(r#""affiliation":["student"]"#));
+        assert!(decode_token(&token, "1:c2VjcmV0", Some("discord".to_owned()), Some("5678".to_owned()), None, None).is_err());
+        assert!(decode_token(&token, "1:c2VjcmV0", None, None, Some("guild-1".to_owned()), None).is_ok());
+        assert!(decode_token(&token, "0:b2xk", None, None, None, None).is_err());
+    }
+
+    #[test]
+    fn decodes_ed25519_tokens_with_public_keys() {
+        let rng = ring::rand::SystemRandom::new();
+        let pkcs8 = ring::signature::Ed25519KeyPair::generate_pkcs8(&rng).unwrap();
+        let signing_key = SigningKey::from_pkcs8(2, pkcs8.as_ref()).unwrap();
+        let claims: VerifiedClaims = serde_json::from_str(&claims_json()).unwrap();
+        let token = crate::encode_token(&claims, &signing_key);
+
+        let public_keys = format!("2:{}", base64::encode_config(signing_key.public_key(), base64::URL_SAFE_NO_PAD));
+        assert!(decode_public_token(&token, &public_keys, None, None, None).is_ok());
+        assert!(decode_public_token(&token, "2:c2VjcmV0", None, None, None).is_err());
+        assert!(decode_token(&token, "2:c2VjcmV0", None, None, None, None).is_err());
+    }
+
+    #[test]
+    fn opens_sealed_tokens() {
+        let claims: VerifiedClaims = serde_json::from_str(&claims_json()).unwrap();
+        let keys = format!("1:{},2:{}", base64::encode_config([7; 32], base64::URL_SAFE_NO_PAD), base64::encode_config([8; 32], base64::URL_SAFE_NO_PAD));
+        let token = crate::seal_token(&claims, &SealingKeyring::new(2, &[8; 32]).unwrap());
+
+        let opened = open_sealed_token(&token, &keys, Some("discord".to_owned()), Some("1234".to_owned()), None).unwrap();
+        assert!(opened.contains(r#""affiliation":["student"]"#));
+        assert!(open_sealed_token(&token, &keys, Some("discord".to_owned()), Some("5678".to_owned()), None).is_err());
+        assert!(open_sealed_token(&token, "2:c2VjcmV0", None, None, None).is_err());
+        assert!(decode_token(&token, "2:c2VjcmV0", None, None, None, None).is_err());
+    }
+
+    #[test]
+    fn accepts_unversioned_tokens_until_legacy_deadline() {
+        #[derive(serde::Serialize)]
+        struct Unversioned(Vec<u8>, Vec<String>, Vec<String>, Vec<String>);
+
+        let mut data = rmp_serde::to_vec(&Unversioned(vec![1, 2, 3], vec![], vec![], vec!["student".to_owned()])).unwrap();
+        let tag = ring::hmac::sign(&ring::hmac::Key::new(ring::hmac::HMAC_SHA256, b"secret"), &data);
+        data.extend_from_slice(tag.as_ref());
+        let token = base64::encode_config(data, base64::URL_SAFE_NO_PAD);
+        let tomorrow = format!("0:{}", SystemClock.now() + 24 * 60 * 60);
+
+        assert!(decode_token(&token, "0:c2VjcmV0", None, None, None, None).is_err());
+        let claims = decode_token(&token, "0:c2VjcmV0", None, None, None, Some(tomorrow)).unwrap();
+        assert!(claims.contains(r#""affiliation":["student"]"#));
+        assert!(decode_token(&token, "0:c2VjcmV0", None, None, None, Some("0:1000".to_owned())).is_err());
+        assert!(decode_token(&token, "0:c2VjcmV0", None, None, None, Some("1:4102444800".to_owned())).is_err());
+        assert!(decode_token(&token, "0:c2VjcmV0", None, None, None, Some("0".to_owned())).is_err());
+    }
+}
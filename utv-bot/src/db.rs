@@ -3,12 +3,30 @@ use std::collections::HashMap;
 use aws_sdk_dynamodb::{model::AttributeValue, Client};
 use serde::Deserialize;
 use serenity::model::id::{GuildId, RoleId};
+use utv_token::Affiliation;
 
 #[derive(Deserialize, Debug)]
 pub struct Claims {
     pub major: Vec<String>,
     pub school: Vec<String>,
-    pub affiliation: Vec<String>,
+    pub affiliation: Vec<Affiliation>,
+    #[serde(default)]
+    pub classification: Vec<String>,
+    #[serde(default)]
+    pub department: Vec<String>,
+    #[serde(default)]
+    pub title: Vec<String>,
+    #[serde(default)]
+    pub graduation: Vec<String>,
+    /// Guilds the verification is restricted to, or empty if it is valid in every guild
+    #[serde(default)]
+    pub aud: Vec<String>,
+}
+
+impl Claims {
+    pub fn valid_for(&self, guild_id: GuildId) -> bool {
+        self.aud.is_empty() || self.aud.contains(&guild_id.0.to_string())
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -71,7 +89,15 @@ impl DynamoDB {
             .map(|m| m.clone())
             .map(|m| {
                 let mut output: HashMap<String, u64> = HashMap::new();
-                let keys = ["affiliation_roles", "school_roles", "major_roles"];
+                let keys = [
+                    "affiliation_roles",
+                    "school_roles",
+                    "major_roles",
+                    "classification_roles",
+                    "department_roles",
+                    "title_roles",
+                    "graduation_roles",
+                ];
                 for key in keys {
                     let temp: HashMap<String, u64> = match m.get(key) {
                         Some(AttributeValue::S(data)) => {
@@ -96,3 +122,4 @@ impl DynamoDB {
 // major_roles: JSON {"Computer Science, Entry-Level": 32094209878097, "Computer Science":
 // 348023984093}
 // school_roles: JSON {"College of Natural Science": 340580932480}
+// classification_roles, department_roles, title_roles, graduation_roles: JSON, likewise
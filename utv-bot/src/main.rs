@@ -27,6 +27,7 @@ use serenity::{
     },
     prelude::*,
 };
+use utv_token::Affiliation;
 
 const REQUESTS_PER_SECOND: i32 = 10;
 const SQS_BECOME_VERIFIED_REQUEST_URL: &'static str = "https://sqs.us-east-1.amazonaws.com/402762806873/on-verification-update";
@@ -128,11 +129,16 @@ async fn handle_member_status(
         .replace(|c: char| !c.is_ascii(), "")
         .trim()
         .to_string();
-    if let Some(user_claims) = db_client.get_user(mem.user.id.into()).await {
+    let user_claims = db_client.get_user(mem.user.id.into()).await;
+    if let Some(user_claims) = user_claims.filter(|claims| claims.valid_for(mem.guild_id)) {
         let mut roles_to_add = Vec::new();
-        let mut user_tags = user_claims.affiliation.clone();
+        let mut user_tags: Vec<String> = user_claims.affiliation.iter().map(|a| a.to_string()).collect();
         user_tags.extend(user_claims.major.clone());
         user_tags.extend(user_claims.school.clone());
+        user_tags.extend(user_claims.classification.clone());
+        user_tags.extend(user_claims.department.clone());
+        user_tags.extend(user_claims.title.clone());
+        user_tags.extend(user_claims.graduation.clone());
         for tag in &user_tags {
             if let Some(role_id) = role_mappings.get(tag) {
                 if !mem.roles.contains(&RoleId(*role_id)) {
@@ -143,7 +149,7 @@ async fn handle_member_status(
         if roles_to_add.len() > 0 && !mem.add_roles(&ctx.http, &roles_to_add).await.is_ok() {
             eprintln!("Failed to Add Roles to {}", original);
         }
-        if user_claims.affiliation.contains(&"student".to_string()) {
+        if user_claims.affiliation.contains(&Affiliation::Student) {
             cleaned.push_str(" ✓");
         } else {
             return true;
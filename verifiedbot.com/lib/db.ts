@@ -22,7 +22,11 @@ export interface GuildRoles {
   guild_id: String;
   affiliation_roles: {String: number};
   school_roles: {String: number};
-  major_roles: {String: number}
+  major_roles: {String: number};
+  classification_roles?: {String: number};
+  department_roles?: {String: number};
+  title_roles?: {String: number};
+  graduation_roles?: {String: number};
 }
 
 export const docClient = new DynamoDB.DocumentClient();
@@ -43,7 +47,16 @@ export const getGuildRoles = async (guild_id: string) => {
 
 export const setGuildRolesAttribute = async (guild_id: string, attribute: string, data: {String: number}) => {
   // guard against other table changes
-  if (!["affiliation_roles", "major_roles", "school_roles"].includes(attribute)) {
+  const attributes = [
+    "affiliation_roles",
+    "major_roles",
+    "school_roles",
+    "classification_roles",
+    "department_roles",
+    "title_roles",
+    "graduation_roles",
+  ];
+  if (!attributes.includes(attribute)) {
     return null;
   }
   let _ = await docClient.update({
@@ -69,12 +82,13 @@ const DISCORD_ID_SQS_URL =
  * by the verification server.
  * 
  * @param eid The user's ut eid
+ * @param discord_id The Discord account the emailed token may be redeemed for
  */
-export const requestToken = async (eid: string) => {
+export const requestToken = async (eid: string, discord_id: string) => {
   const sqs = new SQS();
   const _res = await sqs.sendMessage({
     QueueUrl: EID_SQS_URL,
-    MessageBody: JSON.stringify({eid})
+    MessageBody: JSON.stringify({eid, service: "discord", service_id: discord_id})
   }).promise();
 };
 
@@ -1,30 +1,49 @@
-import crypto from "crypto";
-import {decode} from "@msgpack/msgpack";
+// Built from ../ut-verification-token with `wasm-pack build --target nodejs` by `npm install`,
+// so that tokens are decoded by the same code that ut-verification-server uses to encode them.
+import { decode_public_token, decode_token, open_sealed_token } from "utv_token";
 
 export interface VerifiedClaims {
   encrypted_eid: number[],
   major: String[],
   school: String[],
-  affiliation: String[]
+  affiliation: String[],
+  iat: number,
+  exp: number,
+  service: String,
+  service_id: String,
+  jti: String,
+  aud?: String[],
+  classification?: String[],
+  department?: String[],
+  title?: String[],
+  graduation?: String[],
+  institution?: String
 }
 
-const key = Buffer.from(process.env.SHARED_KEY!, "base64url");
+// "id:key,...", matching ut-verification-server's SEALING_KEYS when it encrypts tokens
+const sealingKeys = process.env.SEALING_KEYS;
+// "id:public key,...", when ut-verification-server signs tokens with an Ed25519 SIGNING_KEY
+const publicKeys = process.env.PUBLIC_KEYS;
+// "id:key,id:key", matching the keyring used by ut-verification-server, which treats a lone
+// SHARED_KEY as key 0
+const keys = process.env.SHARED_KEYS ?? `0:${process.env.SHARED_KEY!}`;
+// "id:deadline" to accept unversioned tokens signed with key `id` until the unix time `deadline`
+const legacy = process.env.LEGACY_KEY;
 
-export function decodeToken(token: String): VerifiedClaims | false {
-  const buf = Buffer.from(token, "base64url");
-  const data = buf.slice(0, buf.length - 32);
-  const inputHash = buf.slice(buf.length - 32);
-
-  const hmac = crypto.createHmac("sha256", key);
-  hmac.update(data);
-  const validHash = hmac.digest();
-  if (!crypto.timingSafeEqual(inputHash, validHash)) return false;
-
-  const [encrypted_eid, major, school, affiliation] = decode(data) as [number[], String[], String[], String[]];
-  return {
-    encrypted_eid,
-    major,
-    school,
-    affiliation
+/**
+ * Checks a token's signature (or decrypts a sealed token) and its expiry, returning its claims.
+ *
+ * @param discord_id When given, the token is only accepted if it was issued for this Discord account
+ */
+export function decodeToken(token: String, discord_id?: string): VerifiedClaims | false {
+  try {
+    const claims = sealingKeys
+      ? open_sealed_token(token.toString(), sealingKeys, discord_id && "discord", discord_id, undefined)
+      : publicKeys
+      ? decode_public_token(token.toString(), publicKeys, discord_id && "discord", discord_id, undefined)
+      : decode_token(token.toString(), keys, discord_id && "discord", discord_id, undefined, legacy);
+    return JSON.parse(claims) as VerifiedClaims;
+  } catch (e) {
+    return false;
   }
 }
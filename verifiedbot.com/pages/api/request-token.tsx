@@ -34,7 +34,7 @@ const handler: NextApiHandler = withIronSessionApiRoute(async (req, res) => {
       ReturnValues: "ALL_NEW"
     }).promise() as any as User;
 
-    await requestToken(eid);
+    await requestToken(eid, discord_id);
 
     res.status(200).send("Verification sent.");
   } catch (e) {